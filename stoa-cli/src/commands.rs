@@ -1,10 +1,132 @@
-use clap::Parser;
-use std::path::PathBuf;
+mod ast;
+mod check;
+mod convert;
+mod diff;
+mod expand;
+mod export;
+mod fmt;
+mod get;
+mod graph;
+mod ids;
+mod import;
+mod merge;
+mod patch;
+mod set;
+mod tokens;
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use stoa_core::{
+    keyvalue::{BlockValue, KeyValueBlock},
+    query::{self, KeyPath},
+};
+
+use crate::error::{Error, Result};
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success
+  1  the document is invalid, a check failed or a merge conflicted
+  2  command line usage error
+  3  the requested key was not found
+  4  a file or database could not be read or written";
 
 #[derive(Debug, Parser)]
+#[command(name = "stoa", version, about = "Query, check and rewrite stoa documents")]
+#[command(after_help = EXIT_CODES)]
 pub struct CommandLine {
-    #[clap(short = 'q')]
-    pub query: Option<String>,
-    #[clap(short = 'f')]
-    pub file: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the value stored at a key path
+    Get(get::GetArgs),
+    /// Replace the value stored at a key path
+    Set(set::SetArgs),
+    /// Check the syntax, lints, macro expansion, ids, references and schema of documents
+    Check(check::CheckArgs),
+    /// Rewrite a document in the canonical layout
+    Fmt(fmt::FmtArgs),
+    /// Print a document with all macro calls expanded
+    Expand(expand::ExpandArgs),
+    /// Convert a document to another format
+    Convert(convert::ConvertArgs),
+    /// Show what changed between two documents, by key path
+    Diff(diff::DiffArgs),
+    /// Apply a patch written by `stoa diff --format patch`
+    Patch(patch::PatchArgs),
+    /// Merge two documents' changes to a common ancestor, key by key
+    Merge(merge::MergeArgs),
+    /// Export a document to another language, such as SQL
+    Export(export::ExportArgs),
+    /// Describe the tables of an existing database as a stoa document
+    Import(import::ImportArgs),
+    /// Draw the references between entries as a DOT or Mermaid graph
+    Graph(graph::GraphArgs),
+    /// Give entries without an `id` the next one of their type
+    Ids(ids::IdsArgs),
+    /// Print the token stream of a document
+    Tokens(tokens::TokensArgs),
+    /// Print the parsed tree of a document
+    Ast(ast::AstArgs),
+}
+
+impl Command {
+    pub fn run(self) -> Result<()> {
+        match self {
+            Self::Get(args) => get::run(args),
+            Self::Set(args) => set::run(args),
+            Self::Check(args) => check::run(args),
+            Self::Fmt(args) => fmt::run(args),
+            Self::Expand(args) => expand::run(args),
+            Self::Convert(args) => convert::run(args),
+            Self::Diff(args) => diff::run(args),
+            Self::Patch(args) => patch::run(args),
+            Self::Merge(args) => merge::run(args),
+            Self::Export(args) => export::run(args),
+            Self::Import(args) => import::run(args),
+            Self::Graph(args) => graph::run(args),
+            Self::Ids(args) => ids::run(args),
+            Self::Tokens(args) => tokens::run(args),
+            Self::Ast(args) => ast::run(args),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Readable text
+    Human,
+    /// JSON, for other tools to consume
+    Json,
+}
+
+pub fn print_json(value: &impl serde::Serialize) -> Result<()> {
+    let json = serde_json::to_string_pretty(value).expect("stoa values always serialise");
+    writeln!(io::stdout().lock(), "{json}").map_err(stdout_error)
+}
+
+/// An error writing to standard output. A reader that stopped early, such
+/// as `head`, is not reported.
+pub fn stdout_error(e: io::Error) -> Error {
+    Error::Io(PathBuf::from("<stdout>"), e)
+}
+
+pub fn key_path(query: &str) -> Result<Vec<String>> {
+    query::parse_key_path(query).ok_or_else(|| Error::InvalidKeyPath(query.to_string()))
+}
+
+pub fn lookup<'a>(document: &'a KeyValueBlock, path: &KeyPath, query: &str) -> Result<&'a BlockValue> {
+    query::get(document, path).ok_or_else(|| Error::KeyNotFound(query.to_string()))
+}
+
+pub fn write(path: &Path, contents: &str) -> Result<()> {
+    fs::write(path, contents).map_err(|e| Error::Io(path.to_path_buf(), e))
 }
@@ -0,0 +1,34 @@
+use clap::Args;
+use stoa_core::r#macro::expand_macros;
+
+use super::{print_json, OutputFormat};
+use crate::{
+    error::Result,
+    input::{self, Input},
+};
+
+#[derive(Debug, Args)]
+pub struct AstArgs {
+    #[command(flatten)]
+    pub input: Input,
+    /// Print the tree after macro expansion rather than as parsed
+    #[arg(long)]
+    pub expanded: bool,
+    /// How to print the tree
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+}
+
+pub fn run(args: AstArgs) -> Result<()> {
+    let mut sources = args.input.sources()?;
+    let mut document = input::concatenate(&mut sources)?;
+    if args.expanded {
+        expand_macros(&mut document).map_err(|e| input::document_error(&sources, e))?;
+    }
+
+    match args.format {
+        OutputFormat::Human => print!("{}", document.pretty_string()),
+        OutputFormat::Json => print_json(&document)?,
+    }
+    Ok(())
+}
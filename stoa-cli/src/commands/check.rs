@@ -0,0 +1,246 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use serde_json::{json, Value};
+use stoa_core::{
+    diagnostic::{Diagnostic, DiagnosticCategory, Renderer, Severity},
+    check,
+    lint::LintConfig,
+    schema::Schema,
+    token::{SourceLoc, Span},
+};
+
+use super::print_json;
+use crate::{
+    error::{self, Error, Result},
+    input::{self, DocumentInput, Source},
+};
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    #[command(flatten)]
+    pub input: DocumentInput,
+    /// How to print the diagnostics
+    #[arg(long, value_enum, default_value_t = CheckFormat::Human)]
+    pub format: CheckFormat,
+    #[command(flatten)]
+    pub lints: LintArgs,
+    /// A stoa document of schemas to validate the inputs against
+    #[arg(long, value_name = "PATH")]
+    pub schema: Option<PathBuf>,
+}
+
+/// Lint levels given on the command line. They override the config file, and
+/// are applied allow first, then warn, then deny.
+#[derive(Debug, Args)]
+pub struct LintArgs {
+    /// A stoa document whose `lints` block sets the level of each lint rule
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Turn off a lint rule, given by name or code
+    #[arg(short = 'A', long = "allow", value_name = "RULE")]
+    pub allow: Vec<String>,
+    /// Report a lint rule as a warning
+    #[arg(short = 'W', long = "warn", value_name = "RULE")]
+    pub warn: Vec<String>,
+    /// Report a lint rule as an error
+    #[arg(short = 'D', long = "deny", value_name = "RULE")]
+    pub deny: Vec<String>,
+}
+
+impl LintArgs {
+    fn config(&self) -> Result<LintConfig> {
+        let mut config = match &self.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path).map_err(|e| Error::Io(path.clone(), e))?;
+                let source = Source { path: Some(path.clone()), text, id: 0 };
+                LintConfig::from_block(&source.parse()?).map_err(|e| Error::Stoa(path.clone(), e))?
+            }
+            None => LintConfig::default(),
+        };
+
+        let levels = [(&self.allow, None), (&self.warn, Some(Severity::Warning)), (&self.deny, Some(Severity::Error))];
+        for (rules, level) in levels {
+            for rule in rules {
+                let lint = DiagnosticCategory::from_lint_name(rule).ok_or_else(|| Error::UnknownLint(rule.clone()))?;
+                config.set(lint, level);
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CheckFormat {
+    /// Source snippets with the problem underlined
+    Human,
+    /// A JSON array of diagnostics
+    Json,
+    /// A SARIF 2.1.0 log, for code scanning and editors
+    Sarif,
+}
+
+/// A diagnostic along with the file it was reported in.
+#[derive(Serialize)]
+struct Report<'a> {
+    file: PathBuf,
+    code: &'static str,
+    #[serde(flatten)]
+    diagnostic: &'a Diagnostic,
+}
+
+/// Reads a schema document, adding it to `sources` after the inputs so that
+/// diagnostics can point into it.
+fn schema(path: &Path, sources: &mut Vec<Source>) -> Result<Schema> {
+    let document = input::read_document(path, sources)?;
+    Schema::from_block(&document).map_err(|e| Error::Stoa(path.to_path_buf(), e))
+}
+
+pub fn run(args: CheckArgs) -> Result<()> {
+    let config = args.lints.config()?;
+    let ids = args.input.id_lock()?;
+    let mut sources = args.input.input.sources()?;
+    let inputs = sources.len();
+    let (document, mut diagnostics) = input::load(&mut sources, 0)?;
+    let schema = args.schema.as_deref().map(|path| schema(path, &mut sources)).transpose()?;
+
+    // A partly read document would only produce misleading lints, so the
+    // later stages only run once every input has been read.
+    if diagnostics.is_empty() {
+        diagnostics = check::check(document, &config, args.input.duplicates.policy(), schema.as_ref(), ids.as_ref()).diagnostics;
+    }
+    let count = |severity| diagnostics.iter().filter(|d| d.severity() == severity).count();
+    let errors = count(Severity::Error);
+
+    match args.format {
+        CheckFormat::Human => {
+            let names = sources.iter().map(|s| s.name().display().to_string()).collect::<Vec<_>>();
+            let mut renderer = Renderer::new(&names[0], &sources[0].text).with_colour(error::use_colour());
+            for (name, source) in names.iter().zip(&sources).skip(1) {
+                renderer = renderer.with_file(name, &source.text);
+            }
+            for diagnostic in &diagnostics {
+                eprintln!("{}", renderer.render(diagnostic));
+            }
+            let summary = [(errors, "error"), (count(Severity::Warning), "warning"), (count(Severity::Note), "note")]
+                .into_iter()
+                .filter(|(n, _)| *n > 0)
+                .map(|(n, what)| plural(n, what))
+                .collect::<Vec<_>>();
+            let summary = match summary.is_empty() {
+                true => "ok".to_string(),
+                false => summary.join(", "),
+            };
+            println!("{} checked: {summary}", plural(inputs, "file"));
+        }
+        CheckFormat::Json => {
+            let reports = diagnostics.iter().map(|diagnostic| report(&sources, diagnostic)).collect::<Vec<_>>();
+            print_json(&reports)?;
+        }
+        CheckFormat::Sarif => print_json(&sarif(&sources, &diagnostics))?,
+    }
+
+    match errors {
+        0 => Ok(()),
+        n => Err(Error::CheckFailed(n)),
+    }
+}
+
+/// A diagnostic as JSON, with the file of each of its related locations,
+/// which may be another input than its own.
+fn report(sources: &[Source], diagnostic: &Diagnostic) -> Value {
+    let report = Report { file: source_of(sources, diagnostic.location()).name(), code: diagnostic.code(), diagnostic };
+    let mut report = serde_json::to_value(report).expect("diagnostics always serialise");
+    if let Some(related) = report["related"].as_array_mut() {
+        for (entry, related) in related.iter_mut().zip(diagnostic.related()) {
+            entry["file"] = json!(source_of(sources, related.location).name());
+        }
+    }
+    report
+}
+
+fn plural(n: usize, what: &str) -> String {
+    match n {
+        1 => format!("1 {what}"),
+        n => format!("{n} {what}s"),
+    }
+}
+
+fn source_of(sources: &[Source], location: SourceLoc) -> &Source {
+    input::source_of(sources, location).expect("diagnostics are located in an input")
+}
+
+fn uri(source: &Source) -> String {
+    source.name().display().to_string().replace('\\', "/")
+}
+
+fn sarif(sources: &[Source], diagnostics: &[Diagnostic]) -> Value {
+    let mut rules: Vec<Value> = vec![];
+    let mut results = vec![];
+
+    for diagnostic in diagnostics {
+        let source = source_of(sources, diagnostic.location());
+        let code = diagnostic.code();
+        if !rules.iter().any(|rule| rule["id"] == code) {
+            rules.push(json!({
+                "id": code,
+                "name": format!("{:?}", diagnostic.category()),
+            }));
+        }
+
+        let related = diagnostic
+            .related()
+            .iter()
+            .map(|related| {
+                let source = source_of(sources, related.location);
+                let mut location = location(&uri(source), &source.text, related.location, related.span);
+                location["message"] = json!({ "text": related.message });
+                location
+            })
+            .collect::<Vec<_>>();
+        results.push(json!({
+            "ruleId": code,
+            "level": match diagnostic.severity() {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+                Severity::Note => "note",
+            },
+            "message": { "text": diagnostic.message() },
+            "locations": [location(&uri(source), &source.text, diagnostic.location(), diagnostic.span())],
+            "relatedLocations": related,
+        }));
+    }
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "stoa",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+fn location(uri: &str, text: &str, at: SourceLoc, span: Span) -> Value {
+    let mut region = json!({
+        "startLine": at.line(),
+        "startColumn": at.column(),
+    });
+    if span.end > span.start {
+        region["charOffset"] = json!(text[..span.start].chars().count());
+        region["charLength"] = json!(text[span.start..span.end].chars().count());
+    }
+    json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": uri },
+            "region": region,
+        }
+    })
+}
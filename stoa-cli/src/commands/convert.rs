@@ -0,0 +1,59 @@
+use clap::{Args, ValueEnum};
+use serde_json::{Map, Value};
+use stoa_core::keyvalue::{BlockValue, KeyValueBlock};
+
+use crate::{error::Result, input::DocumentInput};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    #[command(flatten)]
+    pub input: DocumentInput,
+    /// The output format
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    pub to: Format,
+    /// Indent the output for reading
+    #[arg(long)]
+    pub pretty: bool,
+}
+
+pub fn run(args: ConvertArgs) -> Result<()> {
+    let document = args.input.document()?;
+
+    match args.to {
+        Format::Json => {
+            let value = block_to_json(&document);
+            let output = if args.pretty {
+                serde_json::to_string_pretty(&value)
+            } else {
+                serde_json::to_string(&value)
+            };
+            println!("{}", output.expect("JSON values always serialise"));
+        }
+    }
+    Ok(())
+}
+
+fn block_to_json(block: &KeyValueBlock) -> Value {
+    let mut map = Map::new();
+    for entry in &block.entries {
+        map.insert(entry.key().to_string(), value_to_json(entry.value()));
+    }
+    Value::Object(map)
+}
+
+fn value_to_json(value: &BlockValue) -> Value {
+    match value {
+        BlockValue::Literal(s) | BlockValue::Expression(s) => Value::String(s.clone()),
+        BlockValue::Integer(n) => Value::from(*n),
+        BlockValue::Float(n) => Value::from(*n),
+        BlockValue::MacroValue(p) => Value::String(format!("${p}")),
+        BlockValue::Reference(path) => Value::String(format!("&{}", path.join("."))),
+        BlockValue::Block(block) => block_to_json(block),
+        BlockValue::Empty => Value::Null,
+    }
+}
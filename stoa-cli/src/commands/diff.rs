@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use stoa_core::{
+    diff::{self, Change},
+    keyvalue::BlockValue,
+    patch::Patch,
+    printer::value_to_source,
+};
+
+use super::print_json;
+use crate::{error::Result, input};
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// The document before the change
+    pub old: PathBuf,
+    /// The document after the change
+    pub new: PathBuf,
+    /// How to print the changes
+    #[arg(long, value_enum, default_value_t = DiffFormat::Human)]
+    pub format: DiffFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiffFormat {
+    /// `-` and `+` lines, like a unified diff
+    Human,
+    /// The changes, for other tools to consume
+    Json,
+    /// A stoa patch, which `stoa patch` applies
+    Patch,
+}
+
+/// Prints what changed between two documents by key path, ignoring layout,
+/// comments and the order of keys. Documents are compared as they are
+/// written, before macros are expanded and without following includes, as
+/// `stoa patch` applies edits, so that every path in the diff can be patched.
+pub fn run(args: DiffArgs) -> Result<()> {
+    let old = input::read_source(&args.old, 0)?.parse()?;
+    let new = input::read_source(&args.new, 1)?.parse()?;
+    let changes = diff::diff(&old, &new);
+    match args.format {
+        DiffFormat::Human => print!("{}", changes.iter().map(describe).collect::<String>()),
+        DiffFormat::Json => print_json(&changes)?,
+        DiffFormat::Patch => print!("{}", Patch::from_changes(changes).to_source()),
+    }
+    Ok(())
+}
+
+/// A change in the style of a unified diff: `-` before the old value and
+/// `+` before the new one.
+fn describe(change: &Change) -> String {
+    match change {
+        Change::Added { path, value } => lines('+', path, value),
+        Change::Removed { path, value } => lines('-', path, value),
+        Change::Changed { path, old, new } => lines('-', path, old) + &lines('+', path, new),
+        Change::Renamed { from, to } => format!("~ {from} renamed to {to}\n"),
+    }
+}
+
+fn lines(sign: char, path: &[String], value: &BlockValue) -> String {
+    let text = match value {
+        BlockValue::Empty => path.join("."),
+        value => format!("{} = {}", path.join("."), value_to_source(value)),
+    };
+    text.lines().map(|line| format!("{sign} {line}\n")).collect()
+}
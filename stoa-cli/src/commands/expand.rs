@@ -0,0 +1,33 @@
+use clap::Args;
+use stoa_core::{
+    printer,
+    r#macro::{expand_macros, expand_macros_annotated},
+};
+
+use crate::{
+    error::Result,
+    input::{self, Input},
+};
+
+#[derive(Debug, Args)]
+pub struct ExpandArgs {
+    #[command(flatten)]
+    pub input: Input,
+    /// Precede the entries each macro call produced with a comment naming the call
+    #[arg(long)]
+    pub annotate: bool,
+}
+
+pub fn run(args: ExpandArgs) -> Result<()> {
+    let mut sources = args.input.sources()?;
+    let mut document = input::concatenate(&mut sources)?;
+    if args.annotate {
+        expand_macros_annotated(&mut document)
+    } else {
+        expand_macros(&mut document)
+    }
+    .map_err(|e| input::document_error(&sources, e))?;
+
+    print!("{}", printer::to_source(&document));
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use clap::{Args, Subcommand, ValueEnum};
+use stoa_core::sql::{self, Dialect};
+
+use crate::{
+    error::Result,
+    input::{self, DocumentInput},
+};
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[command(subcommand)]
+    pub target: Target,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Target {
+    /// Print a CREATE TABLE statement for every entry with `type = "Table"`
+    Sql(SqlArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct SqlArgs {
+    #[command(flatten)]
+    pub input: DocumentInput,
+    /// The database the statements are written for
+    #[arg(long, value_enum, default_value_t = SqlDialect::Sqlite)]
+    pub dialect: SqlDialect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SqlDialect {
+    Sqlite,
+    Postgres,
+}
+
+impl SqlDialect {
+    fn dialect(self) -> Dialect {
+        match self {
+            Self::Sqlite => Dialect::Sqlite,
+            Self::Postgres => Dialect::Postgres,
+        }
+    }
+}
+
+pub fn run(args: ExportArgs) -> Result<()> {
+    match args.target {
+        Target::Sql(args) => {
+            let (document, sources) = args.input.document_and_sources()?;
+            let statements =
+                sql::create_tables(&document, args.dialect.dialect()).map_err(|e| input::document_error(&sources, e))?;
+            print!("{statements}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use clap::Args;
+use stoa_core::printer;
+
+use super::write;
+use crate::{
+    error::{Error, Result},
+    input::Input,
+};
+
+#[derive(Debug, Args)]
+pub struct FmtArgs {
+    #[command(flatten)]
+    pub input: Input,
+    /// Exit with an error instead of printing if a file is not formatted
+    #[arg(long, conflicts_with = "in_place")]
+    pub check: bool,
+    /// Write the result back to each file instead of printing it
+    #[arg(short = 'i', long)]
+    pub in_place: bool,
+}
+
+pub fn run(args: FmtArgs) -> Result<()> {
+    let mut unformatted = vec![];
+
+    for source in args.input.sources()? {
+        let output = printer::to_source(&source.parse()?);
+
+        if args.check {
+            if output != source.text {
+                unformatted.push(source.name());
+            }
+        } else if let (true, Some(path)) = (args.in_place, &source.path) {
+            write(path, &output)?;
+        } else {
+            print!("{output}");
+        }
+    }
+
+    if !unformatted.is_empty() {
+        return Err(Error::NotFormatted(unformatted));
+    }
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use clap::Args;
+use stoa_core::{keyvalue::BlockValue, printer};
+
+use super::{key_path, lookup};
+use crate::input::DocumentInput;
+use crate::error::Result;
+
+#[derive(Debug, Args)]
+pub struct GetArgs {
+    #[command(flatten)]
+    pub input: DocumentInput,
+    /// Dot separated key path, e.g. `Quotes Machines.table`
+    pub path: String,
+}
+
+pub fn run(args: GetArgs) -> Result<()> {
+    let document = args.input.document()?;
+    let path = key_path(&args.path)?;
+
+    match lookup(&document, &path, &args.path)? {
+        // Strings are printed bare so the output can be used directly in scripts.
+        BlockValue::Literal(s) => println!("{s}"),
+        value => println!("{}", printer::value_to_source(value)),
+    }
+    Ok(())
+}
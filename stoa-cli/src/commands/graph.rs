@@ -0,0 +1,34 @@
+use clap::{Args, ValueEnum};
+use stoa_core::graph::Graph;
+
+use super::print_json;
+use crate::{error::Result, input::DocumentInput};
+
+#[derive(Debug, Args)]
+pub struct GraphArgs {
+    #[command(flatten)]
+    pub input: DocumentInput,
+    /// The language to draw the graph in
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz, e.g. `stoa graph -f tables.stoa | dot -Tsvg`
+    Dot,
+    /// A Mermaid flowchart, which Markdown renderers can draw
+    Mermaid,
+    /// The nodes, edges and groups, for other tools to draw
+    Json,
+}
+
+pub fn run(args: GraphArgs) -> Result<()> {
+    let graph = Graph::new(&args.input.document()?);
+    match args.format {
+        GraphFormat::Dot => print!("{}", graph.to_dot()),
+        GraphFormat::Mermaid => print!("{}", graph.to_mermaid()),
+        GraphFormat::Json => print_json(&graph)?,
+    }
+    Ok(())
+}
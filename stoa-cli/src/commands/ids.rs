@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use stoa_core::{edit, ids::IdLock, keyvalue::BlockValue, validate::ID};
+
+use super::write;
+use crate::{
+    error::{Error, Result},
+    input::{self, Duplicates, Input},
+};
+
+#[derive(Debug, Args)]
+pub struct IdsArgs {
+    #[command(flatten)]
+    pub input: Input,
+    /// What to do with a key that is defined more than once in a block
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = Duplicates::LastWins)]
+    pub duplicates: Duplicates,
+    /// Record the ids in this lockfile, creating it if needed. Ids it already
+    /// holds are kept
+    #[arg(long, value_name = "LOCKFILE", conflicts_with = "write")]
+    pub lock: Option<PathBuf>,
+    /// Write `id = N` into each entry in its file. Entries made by macro calls
+    /// have no text of their own, and need a lockfile instead
+    #[arg(long)]
+    pub write: bool,
+}
+
+/// Gives every top-level entry that has a `type` but no `id` the next id of
+/// its type, and prints what was given. Without `--lock` or `--write`
+/// nothing is saved.
+pub fn run(args: IdsArgs) -> Result<()> {
+    let (document, mut sources) = args.input.document_and_sources(args.duplicates.policy())?;
+    let mut lock = match &args.lock {
+        Some(path) if path.exists() => input::read_id_lock(path)?,
+        _ => IdLock::new(),
+    };
+    let assignments = lock.assign(&document);
+
+    if let Some(path) = &args.lock {
+        write(path, &lock.to_source())?;
+    }
+    if args.write {
+        let mut edited = vec![];
+        for assignment in &assignments {
+            let file = assignment.location.file() as usize;
+            let source = &mut sources[file];
+            let Some(path) = source.path.clone() else {
+                return Err(Error::InPlaceStdin);
+            };
+            let key = [assignment.entry.clone(), ID.to_string()];
+            source.text = edit::insert(&source.text, &key, &BlockValue::Integer(assignment.id))
+                .map_err(|e| Error::Stoa(path.clone(), e))?;
+            edited.push((file, path));
+        }
+        edited.dedup();
+        for (file, path) in edited {
+            write(&path, &sources[file].text)?;
+        }
+    }
+
+    for assignment in &assignments {
+        println!("{} `{}`: id {}", assignment.kind, assignment.entry, assignment.id);
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use rusqlite::{Connection, OpenFlags};
+use stoa_core::{
+    printer::to_source,
+    sql::{self, ColumnInfo, ForeignKey, TableInfo},
+};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    #[command(subcommand)]
+    pub source: Source,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Source {
+    /// Print the tables, fields and foreign keys of a SQLite database as
+    /// tables and table occurrences
+    Sqlite(SqliteArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct SqliteArgs {
+    /// The database file, which is opened read only
+    pub database: PathBuf,
+}
+
+pub fn run(args: ImportArgs) -> Result<()> {
+    match args.source {
+        Source::Sqlite(args) => {
+            let error = |e| Error::Sqlite(args.database.clone(), e);
+            let connection = Connection::open_with_flags(&args.database, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(error)?;
+            let tables = read_tables(&connection).map_err(error)?;
+            print!("{}", to_source(&sql::import_tables(&tables)));
+        }
+    }
+    Ok(())
+}
+
+fn read_tables(connection: &Connection) -> rusqlite::Result<Vec<TableInfo>> {
+    let names = connection
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY rowid")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    names.into_iter().map(|name| read_table(connection, name)).collect()
+}
+
+fn read_table(connection: &Connection, name: String) -> rusqlite::Result<TableInfo> {
+    let mut columns = connection
+        .prepare("SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?1) ORDER BY cid")?
+        .query_map([&name], |row| {
+            let column = ColumnInfo {
+                name: row.get(0)?,
+                declared_type: row.get(1)?,
+                primary_key: false,
+                not_null: row.get(2)?,
+                unique: false,
+                default: row.get(3)?,
+            };
+            Ok((column, row.get::<_, i64>(4)? > 0))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    // A key of several columns cannot be marked on any one of them.
+    if columns.iter().filter(|(_, key)| *key).count() == 1 {
+        columns.iter_mut().filter(|(_, key)| *key).for_each(|(column, _)| column.primary_key = true);
+    }
+    let mut columns = columns.into_iter().map(|(column, _)| column).collect::<Vec<_>>();
+
+    for unique in unique_columns(connection, &name)? {
+        columns.iter_mut().filter(|c| c.name == unique).for_each(|c| c.unique = true);
+    }
+
+    // Rows of a key of several columns share an id, and are left out.
+    let rows = connection
+        .prepare("SELECT id, \"table\", \"from\", \"to\" FROM pragma_foreign_key_list(?1) ORDER BY id, seq")?
+        .query_map([&name], |row| {
+            let key = ForeignKey { column: row.get(2)?, table: row.get(1)?, to: row.get(3)? };
+            Ok((row.get::<_, i64>(0)?, key))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let foreign_keys = rows
+        .iter()
+        .filter(|(id, _)| rows.iter().filter(|(other, _)| other == id).count() == 1)
+        .map(|(_, key)| key.clone())
+        .collect();
+
+    Ok(TableInfo { name, columns, foreign_keys })
+}
+
+/// The columns that are unique on their own, other than the primary key.
+fn unique_columns(connection: &Connection, table: &str) -> rusqlite::Result<Vec<String>> {
+    let indexes = connection
+        .prepare("SELECT name FROM pragma_index_list(?1) WHERE \"unique\" AND origin != 'pk'")?
+        .query_map([table], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut columns = vec![];
+    for index in indexes {
+        let mut covered = connection
+            .prepare("SELECT name FROM pragma_index_info(?1)")?
+            .query_map([&index], |row| row.get::<_, Option<String>>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if let [Some(column)] = covered.as_mut_slice() {
+            columns.push(std::mem::take(column));
+        }
+    }
+    Ok(columns)
+}
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use stoa_core::{diff::diff, merge::merge, printer::to_source, token::FileId};
+
+use super::write;
+use crate::{
+    error::{Error, Result},
+    input,
+};
+
+const MERGE_DRIVER: &str = "\
+To merge stoa documents this way in git, add a merge driver:
+  git config merge.stoa.driver 'stoa merge %O %A %B --output %A'
+and use it for stoa files in .gitattributes:
+  *.stoa merge=stoa";
+
+#[derive(Debug, Args)]
+#[command(after_help = MERGE_DRIVER)]
+pub struct MergeArgs {
+    /// The common ancestor of both sides
+    pub base: PathBuf,
+    /// Our side of the merge
+    pub ours: PathBuf,
+    /// Their side of the merge
+    pub theirs: PathBuf,
+    /// Write the merged document to this file instead of printing it
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+    /// How to write keys that were changed differently on each side
+    #[arg(long, value_enum, default_value_t = ConflictStyle::Markers)]
+    pub conflicts: ConflictStyle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConflictStyle {
+    /// Both sides between `<<<<<<<` and `>>>>>>>` lines in the output
+    Markers,
+    /// Our side in the output, with each conflict reported as an error
+    Report,
+}
+
+/// Merges the changes two documents made to a common ancestor, key by key,
+/// and writes the result in the canonical layout, or our side as it was
+/// written if the merge leaves it unchanged. The merge fails if any key was
+/// changed differently on each side, after writing the output.
+pub fn run(args: MergeArgs) -> Result<()> {
+    let mut sources = vec![];
+    for (id, path) in [&args.base, &args.ours, &args.theirs].into_iter().enumerate() {
+        sources.push(input::read_source(path, id as FileId)?);
+    }
+    let ours = sources[1].parse()?;
+    let merged = merge(&sources[0].parse()?, &ours, &sources[2].parse()?);
+
+    let output = match args.conflicts {
+        _ if merged.conflicts.is_empty() && diff(&ours, &merged.document).is_empty() => sources[1].text.clone(),
+        ConflictStyle::Markers => merged.to_source(),
+        ConflictStyle::Report => to_source(&merged.document),
+    };
+    match &args.output {
+        Some(path) => write(path, &output)?,
+        None => print!("{output}"),
+    }
+
+    match (merged.conflicts.len(), args.conflicts) {
+        (0, _) => Ok(()),
+        (n, ConflictStyle::Markers) => Err(Error::MergeConflicts(n)),
+        (_, ConflictStyle::Report) => {
+            let diagnostics = merged.conflicts.iter().map(|c| c.to_diagnostic()).collect();
+            let files = sources.into_iter().map(|s| (s.name(), s.text)).collect();
+            Err(Error::Invalid(files, diagnostics))
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use stoa_core::{patch::Patch, printer::to_source};
+
+use super::write;
+use crate::{
+    error::{Error, Result},
+    input,
+};
+
+#[derive(Debug, Args)]
+pub struct PatchArgs {
+    /// The document to patch
+    pub document: PathBuf,
+    /// The patch to apply, such as one written by `stoa diff --format patch`
+    pub patch: PathBuf,
+    /// Write the result back to the file instead of printing it
+    #[arg(short = 'i', long)]
+    pub in_place: bool,
+}
+
+/// Applies a patch to a document as it is written, before macros are
+/// expanded, and prints the result in the canonical layout. Nothing is
+/// written if any edit conflicts.
+pub fn run(args: PatchArgs) -> Result<()> {
+    let source = input::read_source(&args.document, 0)?;
+    let patch_source = input::read_source(&args.patch, 1)?;
+    let patch = Patch::from_block(&patch_source.parse()?).map_err(|e| Error::Stoa(args.patch.clone(), e))?;
+
+    let mut document = source.parse()?;
+    let conflicts = patch.apply(&mut document);
+    if !conflicts.is_empty() {
+        let files = vec![(source.name(), source.text), (patch_source.name(), patch_source.text)];
+        return Err(Error::Invalid(files, conflicts));
+    }
+
+    let output = to_source(&document);
+    if args.in_place {
+        write(&args.document, &output)
+    } else {
+        print!("{output}");
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use clap::Args;
+use stoa_core::{edit, keyvalue::BlockValue, lexer::lex, parser::parse_single_value, query};
+
+use super::{key_path, write};
+use crate::{
+    error::{Error, Result},
+    input::Input,
+};
+
+#[derive(Debug, Args)]
+pub struct SetArgs {
+    #[command(flatten)]
+    pub input: Input,
+    /// Dot separated key path, e.g. `Quotes.id`
+    pub path: String,
+    /// The new value, written as stoa source (`5`, `"Table"`, `{ id = 1 }`).
+    /// The entry is added if it does not exist yet
+    #[arg(required_unless_present = "remove")]
+    pub value: Option<String>,
+    /// Treat the value as a plain string rather than stoa source
+    #[arg(long)]
+    pub string: bool,
+    /// Remove the entry at the key path instead of setting it
+    #[arg(long, conflicts_with_all = ["value", "string"])]
+    pub remove: bool,
+    /// Write the result back to the file instead of printing it
+    #[arg(short = 'i', long)]
+    pub in_place: bool,
+}
+
+/// Rewrites only the edited entry, leaving comments, ordering and layout in
+/// the rest of the document untouched.
+pub fn run(args: SetArgs) -> Result<()> {
+    let source = args.input.single_source()?;
+    if args.in_place && source.path.is_none() {
+        return Err(Error::InPlaceStdin);
+    }
+    let path = key_path(&args.path)?;
+
+    let output = match &args.value {
+        None => edit::remove(&source.text, &path),
+        Some(value) => {
+            let value = parse_value(value, args.string)?;
+            if query::get(&source.parse()?, &path).is_some() {
+                edit::set(&source.text, &path, &value)
+            } else {
+                edit::insert(&source.text, &path, &value)
+            }
+        }
+    }
+    .map_err(|e| match e {
+        stoa_core::Error::KeyNotFound(_) => Error::KeyNotFound(args.path.clone()),
+        e => Error::Stoa(source.name(), e),
+    })?;
+
+    match (&source.path, args.in_place) {
+        (Some(path), true) => write(path, &output),
+        _ => {
+            print!("{output}");
+            Ok(())
+        }
+    }
+}
+
+fn parse_value(text: &str, string: bool) -> Result<BlockValue> {
+    if string {
+        return Ok(BlockValue::Literal(text.to_string()));
+    }
+    let mut diagnostics = vec![];
+    lex(text, &mut diagnostics)
+        .and_then(|tokens| parse_single_value(&tokens, &mut diagnostics))
+        .and_then(|value| match diagnostics.is_empty() {
+            true => Ok(value),
+            false => Err(stoa_core::Error::SyntaxErrors(diagnostics)),
+        })
+        .map_err(|e| Error::InvalidValue(text.to_string(), e))
+}
@@ -0,0 +1,48 @@
+use std::io::{self, Write};
+
+use clap::Args;
+use serde_json::json;
+
+use super::{print_json, stdout_error, OutputFormat};
+use crate::{error::Result, input::Input};
+
+#[derive(Debug, Args)]
+pub struct TokensArgs {
+    #[command(flatten)]
+    pub input: Input,
+    /// How to print the tokens
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+}
+
+pub fn run(args: TokensArgs) -> Result<()> {
+    let sources = args.input.sources()?;
+
+    match args.format {
+        OutputFormat::Human => {
+            let mut out = io::stdout().lock();
+            for source in &sources {
+                if sources.len() > 1 {
+                    writeln!(out, "==> {} <==", source.name().display()).map_err(stdout_error)?;
+                }
+                for token in source.tokens()? {
+                    let kind = format!("{:?}", token.token_val.kind());
+                    writeln!(out, "{:<8}{:<16}{}", token.source_loc.to_string(), kind, token.token_val).map_err(stdout_error)?;
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let files = sources
+                .iter()
+                .map(|source| {
+                    Ok(json!({
+                        "file": source.name(),
+                        "tokens": source.tokens()?,
+                    }))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            print_json(&files)?;
+        }
+    }
+    Ok(())
+}
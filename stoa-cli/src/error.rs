@@ -1,17 +1,142 @@
+use std::{
+    io::{self, IsTerminal},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use stoa_core::diagnostic::{Diagnostic, Renderer};
+
 pub type Result<T> = ::core::result::Result<T, Error>;
 
+/// The document is malformed, or a check over it did not pass.
+pub const EXIT_INVALID: u8 = 1;
+/// The command line could not be understood. Clap exits with this code too.
+pub const EXIT_USAGE: u8 = 2;
+/// The requested key does not exist in the document.
+pub const EXIT_NOT_FOUND: u8 = 3;
+/// A file could not be read or written.
+pub const EXIT_IO: u8 = 4;
+
 #[derive(Debug)]
 pub enum Error {
-    NoFileSpecified,
-    NoQuerySpecified,
+    Io(PathBuf, std::io::Error),
+    Stoa(PathBuf, stoa_core::Error),
+    /// Diagnostics that make the input invalid, along with the name and text
+    /// of each input, indexed by file id, to render them against.
+    Invalid(Vec<(PathBuf, String)>, Vec<Diagnostic>),
+    Document(stoa_core::Error),
+    Sqlite(PathBuf, rusqlite::Error),
+
+    InvalidPattern(String, glob::PatternError),
+    NoMatches(String),
+    NeedsOneInput(usize),
+    InPlaceStdin,
 
-    UnexpectedEOF,
-    incompleteAppend,
+    InvalidKeyPath(String),
+    UnknownLint(String),
+    InvalidValue(String, stoa_core::Error),
+    KeyNotFound(String),
+    NotFormatted(Vec<PathBuf>),
+    /// `stoa check` found errors. They have already been printed.
+    CheckFailed(usize),
+    /// `stoa merge` wrote this many conflicts with markers.
+    MergeConflicts(usize),
+}
+
+/// Whether standard error is a terminal that should be written in colour.
+pub fn use_colour() -> bool {
+    io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+impl Error {
+    /// The text printed to standard error when a command fails. Diagnostics
+    /// are shown against the offending source lines.
+    pub fn report(&self, colour: bool) -> String {
+        match self {
+            Self::Invalid(files, diagnostics) => {
+                let names = files.iter().map(|(path, _)| path.display().to_string()).collect::<Vec<_>>();
+                let mut renderer = Renderer::new(&names[0], &files[0].1).with_colour(colour);
+                for (name, (_, text)) in names.iter().zip(files).skip(1) {
+                    renderer = renderer.with_file(name, text);
+                }
+                diagnostics
+                    .iter()
+                    .map(|d| renderer.render(d))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            Self::CheckFailed(_) => String::new(),
+            _ if colour => format!("\x1b[1;31merror\x1b[0m: {self}\n"),
+            _ => format!("error: {self}\n"),
+        }
+    }
+
+    /// Whether standard output was closed by its reader, which ends the
+    /// command early but is not a failure.
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(self, Self::Io(_, e) if e.kind() == io::ErrorKind::BrokenPipe)
+    }
+
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Self::Io(..) | Self::Sqlite(..) | Self::NoMatches(_) => EXIT_IO,
+            Self::Stoa(..)
+            | Self::Invalid(..)
+            | Self::Document(_)
+            | Self::NotFormatted(_)
+            | Self::CheckFailed(_)
+            | Self::MergeConflicts(_) => EXIT_INVALID,
+            Self::InvalidPattern(..)
+            | Self::NeedsOneInput(_)
+            | Self::InPlaceStdin
+            | Self::InvalidKeyPath(_)
+            | Self::UnknownLint(_)
+            | Self::InvalidValue(..) => EXIT_USAGE,
+            Self::KeyNotFound(_) => EXIT_NOT_FOUND,
+        })
+    }
 }
 
 impl ::core::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{self:?}")
+        match self {
+            Self::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            Self::Stoa(path, e) if e.location().is_some() => write!(f, "{}:{e}", path.display()),
+            Self::Stoa(path, e) => write!(f, "{}: {e}", path.display()),
+            Self::Invalid(files, diagnostics) => {
+                let lines = diagnostics
+                    .iter()
+                    .map(|d| {
+                        let (path, _) = files.get(d.location().file() as usize).unwrap_or(&files[0]);
+                        format!("{}:{d}", path.display())
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                write!(f, "{lines}")
+            }
+            Self::Document(e) => write!(f, "{e}"),
+            Self::Sqlite(path, e) => write!(f, "{}: {e}", path.display()),
+            Self::InvalidPattern(pattern, e) => write!(f, "invalid pattern `{pattern}`: {e}"),
+            Self::NoMatches(pattern) => write!(f, "no files match `{pattern}`"),
+            Self::NeedsOneInput(n) => write!(f, "expected exactly one input document, found {n}"),
+            Self::InPlaceStdin => write!(f, "standard input cannot be written in place"),
+            Self::InvalidKeyPath(path) => write!(f, "invalid key path `{path}`"),
+            Self::UnknownLint(rule) => write!(f, "unknown lint rule `{rule}`"),
+            Self::InvalidValue(value, e) => write!(f, "invalid value `{value}`: {e}"),
+            Self::KeyNotFound(path) => write!(f, "key `{path}` not found"),
+            Self::NotFormatted(paths) => {
+                let paths = paths
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "not formatted: {paths}")
+            }
+            Self::CheckFailed(1) => write!(f, "check failed with 1 error"),
+            Self::CheckFailed(n) => write!(f, "check failed with {n} errors"),
+            Self::MergeConflicts(1) => write!(f, "merge left 1 conflict to resolve"),
+            Self::MergeConflicts(n) => write!(f, "merge left {n} conflicts to resolve"),
+        }
     }
 }
 
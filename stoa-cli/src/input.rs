@@ -0,0 +1,293 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, ValueEnum};
+use stoa_core::{
+    diagnostic::Diagnostic,
+    ids::IdLock,
+    include::{Loader, OsFileSystem},
+    keyvalue::{DuplicatePolicy, KeyValueBlock},
+    lexer::lex_file,
+    parser::parse,
+    r#macro::expand_macros,
+    token::{FileId, SourceLoc, Token},
+};
+
+use crate::error::{Error, Result};
+
+const STDIN: &str = "-";
+const EXTENSION: &str = "stoa";
+
+#[derive(Debug, Args)]
+pub struct Input {
+    /// A stoa document to read. `-` reads standard input, a directory reads
+    /// every `.stoa` file below it and glob patterns are expanded. Repeat the
+    /// flag to merge several documents in order
+    #[arg(short = 'f', long = "file", value_name = "PATH", required = true)]
+    pub files: Vec<String>,
+}
+
+/// The inputs of a command that reads the merged document.
+#[derive(Debug, Args)]
+pub struct DocumentInput {
+    #[command(flatten)]
+    pub input: Input,
+    /// What to do with a key that is defined more than once in a block
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = Duplicates::LastWins)]
+    pub duplicates: Duplicates,
+    /// Give entries without an `id` the one `stoa ids --lock` allocated them
+    /// in this lockfile
+    #[arg(long, value_name = "LOCKFILE")]
+    pub ids: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Duplicates {
+    /// Reject the document
+    Error,
+    /// Keep only the last value
+    LastWins,
+    /// Merge blocks, as if `+=` had been used
+    Merge,
+}
+
+impl Duplicates {
+    pub fn policy(self) -> DuplicatePolicy {
+        match self {
+            Self::Error => DuplicatePolicy::Error,
+            Self::LastWins => DuplicatePolicy::LastWins,
+            Self::Merge => DuplicatePolicy::Merge,
+        }
+    }
+}
+
+/// The text of a single input, along with where it came from.
+#[derive(Debug)]
+pub struct Source {
+    /// `None` when the text was read from standard input.
+    pub path: Option<PathBuf>,
+    pub text: String,
+    /// The position of this input on the command line, or after them for a
+    /// file one of them includes, which locations in it refer to.
+    pub id: FileId,
+}
+
+impl Source {
+    pub fn name(&self) -> PathBuf {
+        self.path.clone().unwrap_or_else(|| PathBuf::from("<stdin>"))
+    }
+
+    pub fn tokens(&self) -> Result<Vec<Token>> {
+        let mut diagnostics = vec![];
+        let tokens = lex_file(&self.text, self.id, &mut diagnostics).map_err(|e| Error::Stoa(self.name(), e))?;
+        diagnostics.sort_by_key(Diagnostic::location);
+        self.check(diagnostics)?;
+        Ok(tokens)
+    }
+
+    pub fn parse(&self) -> Result<KeyValueBlock> {
+        let (document, diagnostics) = self.read()?;
+        self.check(diagnostics)?;
+        Ok(document)
+    }
+
+    /// Lexes and parses the text, returning whatever could be read along with
+    /// every syntax error found, in source order.
+    pub fn read(&self) -> Result<(KeyValueBlock, Vec<Diagnostic>)> {
+        let mut diagnostics = vec![];
+        let tokens = lex_file(&self.text, self.id, &mut diagnostics).map_err(|e| Error::Stoa(self.name(), e))?;
+        let document = parse(&tokens, &mut diagnostics).map_err(|e| Error::Stoa(self.name(), e))?;
+        diagnostics.sort_by_key(Diagnostic::location);
+        Ok((document, diagnostics))
+    }
+
+    fn check(&self, diagnostics: Vec<Diagnostic>) -> Result<()> {
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Invalid(vec![(self.name(), self.text.clone())], diagnostics))
+        }
+    }
+}
+
+impl Input {
+    /// Reads every input, expanding directories and glob patterns into the
+    /// files they contain in lexical order.
+    pub fn sources(&self) -> Result<Vec<Source>> {
+        let mut sources = vec![];
+        for file in &self.files {
+            if file == STDIN {
+                let mut text = String::new();
+                io::stdin()
+                    .read_to_string(&mut text)
+                    .map_err(|e| Error::Io(PathBuf::from("<stdin>"), e))?;
+                sources.push(Source { path: None, text, id: sources.len() as FileId });
+                continue;
+            }
+            for path in expand(file)? {
+                let text = fs::read_to_string(&path).map_err(|e| Error::Io(path.clone(), e))?;
+                sources.push(Source { path: Some(path), text, id: sources.len() as FileId });
+            }
+        }
+        Ok(sources)
+    }
+
+    /// The only input, for commands that rewrite a single document.
+    pub fn single_source(&self) -> Result<Source> {
+        let mut sources = self.sources()?;
+        if sources.len() != 1 {
+            return Err(Error::NeedsOneInput(sources.len()));
+        }
+        Ok(sources.remove(0))
+    }
+
+    /// The merged document along with the inputs it was read from, to
+    /// attribute later errors to. Macros are expanded, `+=` appends folded
+    /// into their definitions and duplicate keys resolved by `policy`, so
+    /// later inputs extend or override earlier ones.
+    pub fn document_and_sources(&self, policy: DuplicatePolicy) -> Result<(KeyValueBlock, Vec<Source>)> {
+        let mut sources = self.sources()?;
+        let mut document = concatenate(&mut sources)?;
+        resolve(&mut document).map_err(|e| document_error(&sources, e))?;
+
+        let duplicates = document.resolve_duplicates(policy);
+        if policy == DuplicatePolicy::Error && !duplicates.is_empty() {
+            return Err(invalid(&sources, duplicates));
+        }
+        Ok((document, sources))
+    }
+}
+
+impl DocumentInput {
+    /// The merged document, with the ids of the `--ids` lockfile given out.
+    pub fn document(&self) -> Result<KeyValueBlock> {
+        Ok(self.document_and_sources()?.0)
+    }
+
+    /// The merged document along with the inputs it was read from, to
+    /// attribute later errors to.
+    pub fn document_and_sources(&self) -> Result<(KeyValueBlock, Vec<Source>)> {
+        let (mut document, sources) = self.input.document_and_sources(self.duplicates.policy())?;
+        if let Some(ids) = self.id_lock()? {
+            ids.apply(&mut document);
+        }
+        Ok((document, sources))
+    }
+
+    /// The lockfile given by `--ids`, if any.
+    pub fn id_lock(&self) -> Result<Option<IdLock>> {
+        self.ids.as_deref().map(read_id_lock).transpose()
+    }
+}
+
+/// Reads a single document on its own, with the files it includes, macros
+/// expanded and appends folded. It is added to `sources`, after those read
+/// before it.
+pub fn read_document(path: &Path, sources: &mut Vec<Source>) -> Result<KeyValueBlock> {
+    let first = sources.len();
+    sources.push(read_source(path, first as FileId)?);
+    let (mut document, diagnostics) = load(sources, first)?;
+    if !diagnostics.is_empty() {
+        return Err(invalid(sources, diagnostics));
+    }
+    resolve(&mut document).map_err(|e| document_error(sources, e))?;
+    Ok(document)
+}
+
+/// Reads a single file as it is written, to be parsed without following its
+/// includes or expanding its macros.
+pub fn read_source(path: &Path, id: FileId) -> Result<Source> {
+    let text = fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+    Ok(Source { path: Some(path.to_path_buf()), text, id })
+}
+
+/// Reads an id lockfile.
+pub fn read_id_lock(path: &Path) -> Result<IdLock> {
+    let source = read_source(path, 0)?;
+    IdLock::from_block(&source.parse()?).map_err(|e| Error::Stoa(path.to_path_buf(), e))
+}
+
+/// The given inputs parsed and concatenated in order, with the files they
+/// include, which are added to `sources`.
+pub fn concatenate(sources: &mut Vec<Source>) -> Result<KeyValueBlock> {
+    let (document, diagnostics) = load(sources, 0)?;
+    if !diagnostics.is_empty() {
+        return Err(invalid(sources, diagnostics));
+    }
+    Ok(document)
+}
+
+/// Parses `sources` from `first` on, replacing `include` and `import`
+/// directives with the files they name, and concatenates them. Included
+/// files are added to `sources`, and every syntax error or file that could
+/// not be included is returned in source order.
+pub fn load(sources: &mut Vec<Source>, first: usize) -> Result<(KeyValueBlock, Vec<Diagnostic>)> {
+    let mut loader = Loader::new(OsFileSystem);
+    for source in sources.iter() {
+        loader.add(source.name(), source.text.as_str());
+    }
+    let mut document = KeyValueBlock::new();
+    let mut diagnostics = vec![];
+    let loaded = (first..sources.len()).try_for_each(|id| {
+        document.entries.extend(loader.load(id as FileId, &mut diagnostics)?.entries);
+        Ok(())
+    });
+
+    let included = loader.files()[sources.len()..].to_vec();
+    for file in included {
+        sources.push(Source { path: Some(file.path), text: file.text, id: sources.len() as FileId });
+    }
+    loaded.map_err(|e| document_error(sources, e))?;
+    diagnostics.sort_by_key(Diagnostic::location);
+    Ok((document, diagnostics))
+}
+
+fn invalid(sources: &[Source], diagnostics: Vec<Diagnostic>) -> Error {
+    let files = sources.iter().map(|s| (s.name(), s.text.clone())).collect();
+    Error::Invalid(files, diagnostics)
+}
+
+/// Expands macros and folds appends in a merged document.
+pub fn resolve(document: &mut KeyValueBlock) -> stoa_core::Result<()> {
+    expand_macros(document)?;
+    document.resolve_appends()
+}
+
+/// Attributes an error in the merged document to the input it came from.
+pub fn document_error(sources: &[Source], error: stoa_core::Error) -> Error {
+    match error.location().and_then(|location| source_of(sources, location)) {
+        Some(source) => Error::Stoa(source.name(), error),
+        None => Error::Document(error),
+    }
+}
+
+/// The input a location in the merged document refers to.
+pub fn source_of(sources: &[Source], location: SourceLoc) -> Option<&Source> {
+    sources.get(location.file() as usize)
+}
+
+fn expand(file: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(file);
+    let pattern = if path.is_dir() {
+        let dir = glob::Pattern::escape(file);
+        format!("{}/**/*.{EXTENSION}", dir.trim_end_matches(['/', '\\']))
+    } else if path.exists() || !file.contains(['*', '?', '[']) {
+        return Ok(vec![path.to_path_buf()]);
+    } else {
+        file.to_string()
+    };
+
+    let paths = glob::glob(&pattern)
+        .map_err(|e| Error::InvalidPattern(file.to_string(), e))?
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+
+    if paths.is_empty() {
+        return Err(Error::NoMatches(file.to_string()));
+    }
+    Ok(paths)
+}
@@ -1,18 +1,21 @@
 mod commands;
 mod error;
+mod input;
 
-use error::{Error, Result};
+use std::process::ExitCode;
 
 use clap::Parser;
 use commands::CommandLine;
 
-fn main() -> Result<()> {
-    let args = commands::CommandLine::parse();
-    let query = args.query.ok_or(Error::NoQuerySpecified)?;
-    let file = args.file.ok_or(Error::NoFileSpecified)?;
+fn main() -> ExitCode {
+    let args = CommandLine::parse();
 
-    println!("file: {query:}");
-    println!("file: {file:?}");
-
-    Ok(())
+    match args.command.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is_broken_pipe() => ExitCode::SUCCESS,
+        Err(e) => {
+            eprint!("{}", e.report(error::use_colour()));
+            e.exit_code()
+        }
+    }
 }
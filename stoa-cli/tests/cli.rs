@@ -0,0 +1,54 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name)
+}
+
+fn stoa(args: &[&Path]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_stoa")).args(args).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+#[test]
+fn diff_patches_documents_with_macros() {
+    let old = fixture("macro_old.stoa");
+    let new = fixture("macro_new.stoa");
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let patch = dir.join("macro.patch.stoa");
+    let patched = dir.join("macro_patched.stoa");
+
+    let diff = stoa(&[Path::new("diff"), &old, &new, Path::new("--format"), Path::new("patch")]);
+    fs::write(&patch, diff.stdout).unwrap();
+    let output = stoa(&[Path::new("patch"), &old, &patch]);
+    fs::write(&patched, output.stdout).unwrap();
+
+    let diff = stoa(&[Path::new("diff"), &patched, &new]);
+    assert_eq!(String::from_utf8_lossy(&diff.stdout), "");
+}
+
+#[test]
+fn merge_keeps_our_layout_when_nothing_changes() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let (base, ours, theirs) = (dir.join("base.stoa"), dir.join("ours.stoa"), dir.join("theirs.stoa"));
+    let text = "Machines = { id = 2, type = \"Table\" } # trailing\n\nQuotes = { id = 1 }\n";
+    fs::write(&base, "Machines = { id = 2, type = \"Table\" }\nQuotes = { id = 1 }\n").unwrap();
+    fs::write(&ours, text).unwrap();
+    fs::write(&theirs, "Quotes = { id = 1 }\nMachines = { type = \"Table\", id = 2 }\n").unwrap();
+
+    let merged = stoa(&[Path::new("merge"), &base, &ours, &theirs]);
+    assert_eq!(String::from_utf8_lossy(&merged.stdout), text);
+}
+
+#[test]
+fn set_removes_the_comment_of_an_entry() {
+    let file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("comment.stoa");
+    fs::write(&file, "A = {\n  x = 1, # note\n  y = 2\n}\n").unwrap();
+
+    let output = stoa(&[Path::new("set"), Path::new("-f"), &file, Path::new("A.x"), Path::new("--remove")]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "A = {\n  y = 2\n}\n");
+}
@@ -0,0 +1,128 @@
+//! Everything that is verified about a document once it has been parsed.
+
+use crate::{
+    diagnostic::{Diagnostic, Severity},
+    ids::IdLock,
+    keyvalue::{DuplicatePolicy, KeyValueBlock},
+    lint::{self, LintConfig},
+    r#macro::expand_macros,
+    schema::Schema,
+    validate,
+    token::SourceLoc,
+};
+
+/// The outcome of [`check`].
+#[derive(Debug)]
+pub struct Checked {
+    /// The document with macros expanded and appends resolved, or `None` if
+    /// that failed.
+    pub document: Option<KeyValueBlock>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Checked {
+    /// How many diagnostics have the given severity.
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity() == severity).count()
+    }
+}
+
+/// Lints a parsed document, then expands and resolves it, treating keys that
+/// are defined more than once according to `duplicates`. Entries without an
+/// `id` are given the one allocated in `ids`, if any. The result has its ids
+/// and references checked, and is validated against `schema` if one is
+/// given. Every problem is reported as a diagnostic rather than an error, in
+/// source order.
+pub fn check(
+    mut document: KeyValueBlock,
+    lints: &LintConfig,
+    duplicates: DuplicatePolicy,
+    schema: Option<&Schema>,
+    ids: Option<&IdLock>,
+) -> Checked {
+    let mut diagnostics = lint::lint(&document, lints);
+
+    let resolved = expand_macros(&mut document).and_then(|()| document.resolve_appends());
+    let document = match resolved {
+        Ok(()) => {
+            let found = document.resolve_duplicates(duplicates);
+            let rejected = duplicates == DuplicatePolicy::Error && !found.is_empty();
+            diagnostics.extend(found.into_iter().filter_map(|d| match duplicates {
+                DuplicatePolicy::Error => Some(d),
+                _ => lints.level(d.category()).map(|severity| d.with_severity(severity)),
+            }));
+            if let Some(ids) = ids {
+                ids.apply(&mut document);
+            }
+            (!rejected).then_some(document)
+        }
+        Err(e) => {
+            diagnostics.push(Diagnostic::from_error(&e, SourceLoc::new(1, 1)));
+            None
+        }
+    };
+
+    if let Some(document) = &document {
+        diagnostics.extend(validate::ids(document));
+        diagnostics.extend(validate::references(document));
+        if let Some(schema) = schema {
+            diagnostics.extend(schema.validate(document));
+        }
+    }
+
+    diagnostics.sort_by_key(Diagnostic::location);
+    Checked { document, diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        diagnostic::{DiagnosticCategory, Severity},
+        keyvalue::DuplicatePolicy,
+        lint::LintConfig,
+        printer,
+        testing::document,
+        token::SourceLoc,
+    };
+
+    use super::check;
+
+    #[test]
+    fn reports_expansion_errors() {
+        let text = "macro unused = { a = 1 }\n@table(1) = {}\n";
+        let checked = check(document(text), &LintConfig::default(), DuplicatePolicy::LastWins, None, None);
+        assert!(checked.document.is_none());
+        assert_eq!(checked.count(Severity::Warning), 1);
+        assert_eq!(checked.count(Severity::Error), 1);
+        assert_eq!(checked.diagnostics[1].category(), DiagnosticCategory::UndefinedMacro);
+        assert_eq!(checked.diagnostics[1].location(), SourceLoc::new(2, 1));
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let text = "\
+Quotes = { id = 1, fields = { a = {} } }
+Machines = {}
+Quotes = { id = 2, fields = { b = {} } }
+";
+        let checked = check(document(text), &LintConfig::default(), DuplicatePolicy::Error, None, None);
+        assert!(checked.document.is_none());
+        let duplicate = &checked.diagnostics[0];
+        assert_eq!(duplicate.category(), DiagnosticCategory::DuplicateKey);
+        assert_eq!(duplicate.severity(), Severity::Error);
+        assert_eq!(duplicate.location(), SourceLoc::new(3, 1));
+        assert_eq!(duplicate.related()[0].location, SourceLoc::new(1, 1));
+
+        let checked = check(document(text), &LintConfig::default(), DuplicatePolicy::LastWins, None, None);
+        let quotes = checked.document.unwrap().entries.remove(0);
+        assert_eq!(printer::entry_to_source_at(&quotes, 0), "Quotes = {\n  id = 2,\n  fields = {\n    b = {}\n  }\n}");
+
+        let checked = check(document(text), &LintConfig::default(), DuplicatePolicy::Merge, None, None);
+        assert_eq!(checked.count(Severity::Warning), 3);
+        let quotes = checked.document.unwrap().entries.remove(0);
+        assert_eq!(
+            printer::entry_to_source_at(&quotes, 0),
+            "Quotes = {\n  id = 2,\n  fields = {\n    a = {},\n    b = {}\n  }\n}"
+        );
+    }
+}
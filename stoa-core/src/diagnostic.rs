@@ -1,11 +1,568 @@
-use crate::token::SourceLoc;
+use std::fmt;
 
+use crate::error::Error;
+use crate::token::{SourceLoc, Span};
+
+/// How serious a diagnostic is. Only errors stop a document from being used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Note => "note",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum DiagnosticCategory {
+    // Lexing
+    UnexpectedCharacter,
+    UnterminatedString,
+    MalformedNumber,
+    MalformedAppend,
+    MalformedMacroName,
+    MalformedReference,
+
+    // Parsing
+    UnexpectedToken,
+    UnexpectedEOF,
+    UnclosedBlock,
+    /// Several syntax errors reported as one, as when an edit does not parse.
+    SyntaxErrors,
+
+    // Validation
     BadIDFormat,
+    MissingKey,
+    WrongType,
+    DisallowedValue,
+    OutOfRange,
+    UnknownKey,
+    DuplicateID,
+    DanglingReference,
+
+    // Expansion
+    UndefinedMacro,
+    UnboundMacroParameter,
+    MacroBodyNotBlock,
+    InvalidAppend,
+
+    // Editing
+    InvalidEdit,
+    InvalidPatch,
+    PatchConflict,
+    MergeConflict,
+
+    // Configuration
+    InvalidConfig,
+    InvalidSchema,
+
+    // Export
+    CannotExport,
+
+    // Includes
+    CannotInclude,
+    IncludeCycle,
+
+    // Lints. Their severity is configurable, see `lint::LintConfig`.
+    DuplicateKey,
+    UnusedMacro,
+    ShadowedMacro,
+    EmptyBlock,
+    InconsistentKeyCasing,
 }
 
+impl DiagnosticCategory {
+    /// A stable identifier for the category. Codes are never reused, so they
+    /// can be searched for and suppressed by tools.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnexpectedCharacter => "E0101",
+            Self::UnterminatedString => "E0102",
+            Self::MalformedNumber => "E0103",
+            Self::MalformedAppend => "E0104",
+            Self::MalformedMacroName => "E0105",
+            Self::MalformedReference => "E0106",
+            Self::UnexpectedToken => "E0201",
+            Self::UnexpectedEOF => "E0202",
+            Self::UnclosedBlock => "E0203",
+            Self::SyntaxErrors => "E0204",
+            Self::BadIDFormat => "E0301",
+            Self::MissingKey => "E0302",
+            Self::WrongType => "E0303",
+            Self::DisallowedValue => "E0304",
+            Self::OutOfRange => "E0305",
+            Self::UnknownKey => "E0306",
+            Self::DuplicateID => "E0307",
+            Self::DanglingReference => "E0308",
+            Self::UndefinedMacro => "E0401",
+            Self::UnboundMacroParameter => "E0402",
+            Self::MacroBodyNotBlock => "E0403",
+            Self::InvalidAppend => "E0404",
+            Self::InvalidEdit => "E0501",
+            Self::InvalidPatch => "E0502",
+            Self::PatchConflict => "E0503",
+            Self::MergeConflict => "E0504",
+            Self::InvalidConfig => "E0601",
+            Self::InvalidSchema => "E0602",
+            Self::CannotExport => "E0701",
+            Self::CannotInclude => "E0801",
+            Self::IncludeCycle => "E0802",
+            Self::DuplicateKey => "L0001",
+            Self::UnusedMacro => "L0002",
+            Self::ShadowedMacro => "L0003",
+            Self::EmptyBlock => "L0004",
+            Self::InconsistentKeyCasing => "L0005",
+        }
+    }
+
+    /// The severity a diagnostic has unless configured otherwise.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::DuplicateKey | Self::UnusedMacro | Self::ShadowedMacro | Self::InconsistentKeyCasing => {
+                Severity::Warning
+            }
+            Self::EmptyBlock => Severity::Note,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Every lint rule, in code order.
+    pub const LINTS: [Self; 5] = [
+        Self::DuplicateKey,
+        Self::UnusedMacro,
+        Self::ShadowedMacro,
+        Self::EmptyBlock,
+        Self::InconsistentKeyCasing,
+    ];
+
+    /// The name a lint rule is configured by, such as `duplicate_keys`.
+    pub fn lint_name(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::DuplicateKey => "duplicate_keys",
+            Self::UnusedMacro => "unused_macros",
+            Self::ShadowedMacro => "shadowed_macros",
+            Self::EmptyBlock => "empty_blocks",
+            Self::InconsistentKeyCasing => "key_casing",
+            _ => return None,
+        })
+    }
+
+    /// Looks up a lint rule by its name or code.
+    pub fn from_lint_name(name: &str) -> Option<Self> {
+        Self::LINTS
+            .into_iter()
+            .find(|lint| lint.lint_name() == Some(name) || lint.code() == name)
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::UnexpectedCharacter => "unexpected character",
+            Self::UnterminatedString => "unterminated string",
+            Self::MalformedNumber => "malformed number",
+            Self::MalformedAppend => "expected `=` after `+`",
+            Self::MalformedMacroName => "expected a name after",
+            Self::MalformedReference => "expected a key path after",
+            Self::UnexpectedToken => "unexpected",
+            Self::UnexpectedEOF => "unexpected end of input",
+            Self::UnclosedBlock => "unclosed block",
+            Self::SyntaxErrors => "syntax errors",
+            Self::BadIDFormat => "malformed id",
+            Self::MissingKey => "missing key",
+            Self::WrongType => "wrong type",
+            Self::DisallowedValue => "value not allowed",
+            Self::OutOfRange => "value out of range",
+            Self::UnknownKey => "unknown key",
+            Self::DuplicateID => "duplicate id",
+            Self::DanglingReference => "dangling reference",
+            Self::UndefinedMacro => "undefined macro",
+            Self::UnboundMacroParameter => "unbound macro parameter",
+            Self::MacroBodyNotBlock => "macro body is not a block",
+            Self::InvalidAppend => "`+=` can only add a block to another block",
+            Self::InvalidEdit => "invalid edit",
+            Self::InvalidPatch => "invalid patch",
+            Self::PatchConflict => "patch conflict",
+            Self::MergeConflict => "merge conflict",
+            Self::InvalidConfig => "invalid configuration",
+            Self::InvalidSchema => "invalid schema",
+            Self::CannotExport => "cannot export",
+            Self::CannotInclude => "cannot include",
+            Self::IncludeCycle => "include cycle",
+            Self::DuplicateKey => "duplicate key",
+            Self::UnusedMacro => "unused macro",
+            Self::ShadowedMacro => "shadowed macro",
+            Self::EmptyBlock => "empty block",
+            Self::InconsistentKeyCasing => "inconsistent key casing",
+        }
+    }
+}
+
+/// Another place in the source that explains a diagnostic, such as the first
+/// definition of a duplicated key.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Related {
+    pub location: SourceLoc,
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Diagnostic {
     category: DiagnosticCategory,
+    severity: Severity,
     location: SourceLoc,
+    span: Span,
+    /// The offending source text.
     snippet: String,
+    message: String,
+    notes: Vec<String>,
+    help: Option<String>,
+    related: Vec<Related>,
+}
+
+impl Diagnostic {
+    pub fn new(category: DiagnosticCategory, location: SourceLoc, snippet: String) -> Self {
+        let message = match snippet.is_empty() {
+            true => category.describe().to_string(),
+            false => format!("{} `{}`", category.describe(), snippet),
+        };
+        Self {
+            category,
+            severity: category.severity(),
+            location,
+            span: Span::default(),
+            snippet,
+            message,
+            notes: vec![],
+            help: None,
+            related: vec![],
+        }
+    }
+
+    /// Builds a diagnostic for an error that stopped a construct from being
+    /// read. `location` is used when the error does not carry its own.
+    pub fn from_error(error: &Error, location: SourceLoc) -> Self {
+        let category = match error {
+            Error::InFile(_, error) => return Self::from_error(error, location),
+            Error::UnexpectedToken(..) => DiagnosticCategory::UnexpectedToken,
+            Error::SyntaxErrors(diagnostics) if diagnostics.len() == 1 => return diagnostics[0].clone(),
+            Error::UnexpectedEOF(..) => DiagnosticCategory::UnexpectedEOF,
+            Error::SyntaxErrors(_) => DiagnosticCategory::SyntaxErrors,
+            Error::MalformedAppend(_) => DiagnosticCategory::InvalidAppend,
+            Error::UndefinedMacro(..) => DiagnosticCategory::UndefinedMacro,
+            Error::UnboundMacroParameter(..) => DiagnosticCategory::UnboundMacroParameter,
+            Error::MacroBodyNotBlock(..) => DiagnosticCategory::MacroBodyNotBlock,
+            Error::KeyNotFound(_) | Error::KeyExists(_) | Error::NotABlock(..) => DiagnosticCategory::InvalidEdit,
+            Error::InvalidPatch(..) => DiagnosticCategory::InvalidPatch,
+            Error::MissingKey(..) => DiagnosticCategory::MissingKey,
+            Error::WrongType(..) => DiagnosticCategory::WrongType,
+            Error::OutOfRange(..) => DiagnosticCategory::OutOfRange,
+            Error::CannotExport(..) => DiagnosticCategory::CannotExport,
+            Error::InvalidConfig(..) => DiagnosticCategory::InvalidConfig,
+            Error::InvalidSchema(..) => DiagnosticCategory::InvalidSchema,
+        };
+        let diagnostic = Self::new(category, error.location().unwrap_or(location), String::new())
+            .with_message(error.message());
+        match error {
+            Error::UnexpectedToken(token, _) => Self {
+                snippet: token.token_val.to_string(),
+                ..diagnostic.with_span(token.span)
+            },
+            _ => diagnostic,
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    pub fn with_related(mut self, location: SourceLoc, span: Span, message: impl Into<String>) -> Self {
+        self.related.push(Related { location, span, message: message.into() });
+        self
+    }
+
+    pub fn category(&self) -> DiagnosticCategory {
+        self.category
+    }
+
+    pub fn location(&self) -> SourceLoc {
+        self.location
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn snippet(&self) -> &str {
+        &self.snippet
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.category.code()
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    pub fn help(&self) -> Option<&str> {
+        self.help.as_deref()
+    }
+
+    pub fn related(&self) -> &[Related] {
+        &self.related
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders diagnostics against the source they were reported in:
+///
+/// ```text
+/// error[E0201]: unexpected `=`
+///  --> tables.stoa:2:7
+///   |
+/// 2 |   b = = 1,
+///   |       ^
+///   = help: ...
+/// ```
+pub struct Renderer<'a> {
+    /// Names and text of the inputs, indexed by file id.
+    files: Vec<(&'a str, &'a str)>,
+    colour: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(file_name: &'a str, source: &'a str) -> Self {
+        Self { files: vec![(file_name, source)], colour: false }
+    }
+
+    /// Adds the next input, for diagnostics located in one of several files.
+    pub fn with_file(mut self, file_name: &'a str, source: &'a str) -> Self {
+        self.files.push((file_name, source));
+        self
+    }
+
+    /// Whether to emit ANSI colour codes.
+    pub fn with_colour(mut self, colour: bool) -> Self {
+        self.colour = colour;
+        self
+    }
+
+    fn file(&self, location: SourceLoc) -> (&'a str, &'a str) {
+        self.files.get(location.file() as usize).copied().unwrap_or(self.files[0])
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let location = diagnostic.location();
+        let gutter = diagnostic
+            .related()
+            .iter()
+            .map(|related| related.location.line())
+            .fold(location.line(), u32::max)
+            .to_string()
+            .len();
+
+        let severity_colour = match diagnostic.severity() {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => GREEN,
+        };
+        let mut out = format!(
+            "{}{}[{}]{}{}: {}{}\n",
+            self.paint(severity_colour),
+            diagnostic.severity(),
+            diagnostic.code(),
+            self.paint(RESET),
+            self.paint(BOLD),
+            diagnostic.message(),
+            self.paint(RESET),
+        );
+        out += &self.header("-->", location, gutter);
+        out += &self.snippet(location, diagnostic.span(), ('^', severity_colour), "", gutter);
+
+        // Related locations are shown with their own source line, underlined
+        // with dashes and labelled with their message.
+        for related in diagnostic.related() {
+            out += &self.header(":::", related.location, gutter);
+            out += &self.snippet(related.location, related.span, ('-', BLUE), &related.message, gutter);
+        }
+        for note in diagnostic.notes() {
+            out += &format!("{:gutter$} {}={} note: {note}\n", "", self.paint(BLUE), self.paint(RESET));
+        }
+        if let Some(help) = diagnostic.help() {
+            out += &format!("{:gutter$} {}={} help: {help}\n", "", self.paint(BLUE), self.paint(RESET));
+        }
+        out
+    }
+
+    /// ` --> file:line:col`
+    fn header(&self, arrow: &str, location: SourceLoc, gutter: usize) -> String {
+        let file_name = self.file(location).0;
+        format!("{:gutter$}{}{arrow}{} {file_name}:{location}\n", "", self.paint(BLUE), self.paint(RESET))
+    }
+
+    /// The source line at `location` with `span` underlined, or nothing if
+    /// the line does not exist.
+    fn snippet(&self, location: SourceLoc, span: Span, (marker, colour): (char, &'static str), label: &str, gutter: usize) -> String {
+        let source = self.file(location).1;
+        let Some(line) = source.lines().nth(location.line().saturating_sub(1) as usize) else {
+            return String::new();
+        };
+        let bar = format!("{}|{}", self.paint(BLUE), self.paint(RESET));
+        let (start, width) = underline(location, span, source, line);
+        // Keep tabs so the underline lines up with the text above it.
+        let padding: String = line.chars().take(start).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let label = match label.is_empty() {
+            true => String::new(),
+            false => format!(" {label}"),
+        };
+
+        let mut out = format!("{:gutter$} {bar}\n", "");
+        out += &format!("{}{:<gutter$}{} {bar} {line}\n", self.paint(BLUE), location.line(), self.paint(RESET));
+        out += &format!(
+            "{:gutter$} {bar} {padding}{}{}{label}{}\n",
+            "",
+            self.paint(colour),
+            marker.to_string().repeat(width),
+            self.paint(RESET),
+        );
+        out
+    }
+
+    fn paint(&self, code: &'static str) -> &'static str {
+        if self.colour { code } else { "" }
+    }
+}
+
+/// The character column the underline starts at and how many characters it
+/// covers, clamped to the end of `line`.
+fn underline(location: SourceLoc, span: Span, source: &str, line: &str) -> (usize, usize) {
+    let length = line.chars().count();
+    let start = (location.column().saturating_sub(1) as usize).min(length);
+    let width = source
+        .get(span.start..span.end)
+        .map_or(0, |text| text.lines().next().unwrap_or("").chars().count());
+    (start, width.min(length - start).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Error, keyvalue::DuplicatePolicy, lexer::lex, parser::parse, token::SourceLoc};
+
+    use super::{Diagnostic, DiagnosticCategory, Renderer};
+
+    #[test]
+    fn renders_source_and_underline() {
+        let text = "a = {\n  b = = 1,\n  c = \"x\"\n}\nd = \"unterminated\n";
+        let mut diagnostics = vec![];
+        let tokens = lex(text, &mut diagnostics).unwrap();
+        parse(&tokens, &mut diagnostics).unwrap();
+
+        let renderer = Renderer::new("tables.stoa", text);
+        assert_eq!(renderer.render(&diagnostics[1]), "\
+error[E0201]: unexpected `=`, expected one of `{`, an integer, a float, a string, a macro parameter or a reference
+ --> tables.stoa:2:7
+  |
+2 |   b = = 1,
+  |       ^
+");
+        assert_eq!(renderer.render(&diagnostics[0]), "\
+error[E0102]: unterminated string
+ --> tables.stoa:5:5
+  |
+5 | d = \"unterminated
+  |     ^^^^^^^^^^^^^
+");
+    }
+
+    #[test]
+    fn keeps_the_category_of_syntax_errors() {
+        let mut diagnostics = vec![];
+        let tokens = lex("a = = 1\nb = \"x", &mut diagnostics).unwrap();
+        parse(&tokens, &mut diagnostics).unwrap();
+
+        let one = Diagnostic::from_error(&Error::SyntaxErrors(diagnostics[..1].to_vec()), SourceLoc::new(1, 1));
+        assert_eq!(one.code(), diagnostics[0].code());
+        assert_eq!(one.message(), diagnostics[0].message());
+        let count = diagnostics.len();
+        let all = Diagnostic::from_error(&Error::SyntaxErrors(diagnostics), SourceLoc::new(1, 1));
+        assert_eq!(all.category(), DiagnosticCategory::SyntaxErrors);
+        assert_eq!(all.message(), format!("{count} syntax errors"));
+    }
+
+    #[test]
+    fn renders_related_locations() {
+        let text = "Quotes = { id = 1 }\nQuotes = {}\n";
+        let mut diagnostics = vec![];
+        let tokens = lex(text, &mut diagnostics).unwrap();
+        let mut document = parse(&tokens, &mut diagnostics).unwrap();
+        let diagnostics = document.resolve_duplicates(DuplicatePolicy::Error);
+
+        let renderer = Renderer::new("tables.stoa", text);
+        assert_eq!(renderer.render(&diagnostics[0]), "\
+error[L0001]: `Quotes` is defined more than once
+ --> tables.stoa:2:1
+  |
+2 | Quotes = {}
+  | ^^^^^^^^^^^
+ ::: tables.stoa:1:1
+  |
+1 | Quotes = { id = 1 }
+  | ------------------- first defined here
+  = help: use `+=` to add to the first definition
+");
+    }
 }
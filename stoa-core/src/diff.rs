@@ -0,0 +1,174 @@
+//! The differences between two resolved documents, by key path. Values are
+//! compared by meaning: layout, comments and the order of keys in a block
+//! do not count. A top-level entry that keeps its `type` and `id` under a
+//! new name is reported as renamed, along with any changes inside it.
+
+use std::collections::HashMap;
+
+use crate::{
+    keyvalue::{BlockValue, KeyValueBlock, KeyValueEntry},
+    validate,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "change", rename_all = "snake_case"))]
+pub enum Change {
+    Added { path: Vec<String>, value: BlockValue },
+    Removed { path: Vec<String>, value: BlockValue },
+    Changed { path: Vec<String>, old: BlockValue, new: BlockValue },
+    /// A top-level entry with the same `type` and `id` under another name.
+    Renamed { from: String, to: String },
+}
+
+impl Change {
+    /// The key path the change is at, in the new document for a rename.
+    pub fn path(&self) -> Vec<String> {
+        match self {
+            Self::Added { path, .. } | Self::Removed { path, .. } | Self::Changed { path, .. } => path.clone(),
+            Self::Renamed { to, .. } => vec![to.clone()],
+        }
+    }
+}
+
+/// Every change that turns `old` into `new`: first those to entries `old`
+/// has, in its order, then the entries only `new` has, in its order.
+pub fn diff(old: &KeyValueBlock, new: &KeyValueBlock) -> Vec<Change> {
+    let mut changes = vec![];
+    let mut renamed = HashMap::new();
+    for entry in &old.entries {
+        if find(new, entry).is_some() {
+            continue;
+        }
+        let renamed_to = identity(entry).and_then(|wanted| {
+            new.entries.iter().find(|e| {
+                find(old, e).is_none() && identity(e) == Some(wanted) && !renamed.contains_key(&e.key().to_string())
+            })
+        });
+        if let Some(to) = renamed_to {
+            renamed.insert(to.key().to_string(), entry);
+        }
+    }
+
+    for entry in &old.entries {
+        let path = vec![entry.key().to_string()];
+        match find(new, entry) {
+            Some(other) => compare(entry.value(), other.value(), path, &mut changes),
+            None if renamed.values().any(|e| e.key() == entry.key()) => {}
+            None => changes.push(Change::Removed { path, value: entry.value().clone() }),
+        }
+    }
+    for entry in &new.entries {
+        let path = vec![entry.key().to_string()];
+        if find(old, entry).is_some() {
+            continue;
+        }
+        match renamed.get(&path[0]) {
+            Some(from) => {
+                changes.push(Change::Renamed { from: from.key().to_string(), to: path[0].clone() });
+                compare(from.value(), entry.value(), path, &mut changes);
+            }
+            None => changes.push(Change::Added { path, value: entry.value().clone() }),
+        }
+    }
+    changes
+}
+
+/// Whether two values mean the same, ignoring where they were written.
+pub fn same(a: &BlockValue, b: &BlockValue) -> bool {
+    match (a, b) {
+        (BlockValue::Block(a), BlockValue::Block(b)) => {
+            a.entries.iter().all(|e| find(b, e).is_some_and(|other| same(e.value(), other.value())))
+                && b.entries.iter().all(|e| find(a, e).is_some())
+        }
+        (a, b) => a == b,
+    }
+}
+
+fn compare(old: &BlockValue, new: &BlockValue, path: Vec<String>, changes: &mut Vec<Change>) {
+    let (BlockValue::Block(old), BlockValue::Block(new)) = (old, new) else {
+        if !same(old, new) {
+            changes.push(Change::Changed { path, old: old.clone(), new: new.clone() });
+        }
+        return;
+    };
+    let at = |entry: &KeyValueEntry| [path.as_slice(), &[entry.key().to_string()]].concat();
+    for entry in &old.entries {
+        match find(new, entry) {
+            Some(other) => compare(entry.value(), other.value(), at(entry), changes),
+            None => changes.push(Change::Removed { path: at(entry), value: entry.value().clone() }),
+        }
+    }
+    for entry in new.entries.iter().filter(|e| find(old, e).is_none()) {
+        changes.push(Change::Added { path: at(entry), value: entry.value().clone() });
+    }
+}
+
+/// The entry of `block` with the same key as `entry`. The last one wins, as
+/// it does when duplicates are resolved.
+fn find<'a>(block: &'a KeyValueBlock, entry: &KeyValueEntry) -> Option<&'a KeyValueEntry> {
+    block.entries.iter().rev().find(|e| e.key() == entry.key())
+}
+
+/// The `type` and `id` that identify a top-level entry across renames.
+fn identity(entry: &KeyValueEntry) -> Option<(&str, i64)> {
+    match validate::value(entry, validate::ID)?.value() {
+        BlockValue::Integer(id) => Some((validate::entry_type(entry)?, *id)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        keyvalue::BlockValue,
+        testing::document,
+    };
+
+    use super::{diff, same, Change};
+
+    fn path(path: &str) -> Vec<String> {
+        path.split('.').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn ignores_layout_and_order() {
+        let old = document("# Tables\nQuotes = { id = 1, type = \"Table\", fields = { a = 1, b = 2 } }\n");
+        let new = document("Quotes = {\n  fields = {\n    b = 2,\n    a = 1\n  },\n  type = \"Table\",\n  id = 1\n}\n");
+        assert!(diff(&old, &new).is_empty());
+        assert!(same(&BlockValue::Block(old), &BlockValue::Block(new)));
+    }
+
+    #[test]
+    fn reports_changes_by_path() {
+        let old = document(
+            "\
+Quotes = { id = 1, type = \"Table\", fields = { title = \"Text\", total = \"Number\" } }
+Machines = { id = 2, type = \"Table\" }
+Notes = { id = 3, type = \"Table\" }
+",
+        );
+        let new = document(
+            "\
+Quotes = { id = 1, type = \"Table\", fields = { title = \"Text\", total = \"Integer\", made = \"Date\" } }
+Devices = { id = 2, type = \"Table\", kind = \"Press\" }
+Parts = { id = 4, type = \"Table\" }
+",
+        );
+        assert_eq!(
+            diff(&old, &new),
+            [
+                Change::Changed {
+                    path: path("Quotes.fields.total"),
+                    old: BlockValue::Literal("Number".to_string()),
+                    new: BlockValue::Literal("Integer".to_string()),
+                },
+                Change::Added { path: path("Quotes.fields.made"), value: BlockValue::Literal("Date".to_string()) },
+                Change::Removed { path: path("Notes"), value: old.entries[2].value().clone() },
+                Change::Renamed { from: "Machines".to_string(), to: "Devices".to_string() },
+                Change::Added { path: path("Devices.kind"), value: BlockValue::Literal("Press".to_string()) },
+                Change::Added { path: path("Parts"), value: new.entries[2].value().clone() },
+            ]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Edits that rewrite stoa source text in place. Only the bytes of the entry
+//! being changed are touched, so comments, ordering and whitespace elsewhere
+//! in the document survive.
+
+use crate::{
+    error::{Error, Result},
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry},
+    lexer::lex,
+    parser::parse,
+    printer,
+    query::{self, KeyPath},
+    token::{SourceLoc, Span},
+};
+
+/// Replaces the value of the existing entry at `path`.
+pub fn set(source: &str, path: &KeyPath, value: &BlockValue) -> Result<String> {
+    let document = parse_source(source)?;
+    let entry = query::get_entry(&document, path).ok_or_else(|| Error::KeyNotFound(path.to_vec()))?;
+    let Some(span) = entry.value_span() else {
+        return Err(Error::KeyNotFound(path.to_vec()));
+    };
+
+    let indent = indent_of(source, entry.span().start);
+    Ok(splice(source, span, &printer::value_to_source_at(value, indent)))
+}
+
+/// Adds a new entry at `path`. Everything but the last segment of the path
+/// must already exist and name a block.
+pub fn insert(source: &str, path: &KeyPath, value: &BlockValue) -> Result<String> {
+    let document = parse_source(source)?;
+    let Some((key, parent_path)) = path.split_last() else {
+        return Err(Error::KeyNotFound(path.to_vec()));
+    };
+    if query::get_entry(&document, path).is_some() {
+        return Err(Error::KeyExists(path.to_vec()));
+    }
+    let new_entry = KeyValueEntry::new(Key::Name(key.clone()), SourceLoc::new(0, 0), value.clone());
+
+    if parent_path.is_empty() {
+        let mut out = source.trim_end().to_string();
+        if !out.is_empty() {
+            out.push_str("\n\n");
+        }
+        out.push_str(&printer::entry_to_source_at(&new_entry, 0));
+        out.push('\n');
+        return Ok(out);
+    }
+
+    let parent = query::get_entry(&document, parent_path)
+        .ok_or_else(|| Error::KeyNotFound(parent_path.to_vec()))?;
+    let (BlockValue::Block(block), Some(span)) = (parent.value(), parent.value_span()) else {
+        return Err(Error::NotABlock(parent_path.to_vec(), parent.location()));
+    };
+    Ok(insert_into_block(source, block, span, indent_of(source, parent.span().start), &new_entry))
+}
+
+/// Removes the entry at `path` along with its separating comma and any
+/// comment after it on its line, and the whole line if the entry had it to
+/// itself.
+pub fn remove(source: &str, path: &KeyPath) -> Result<String> {
+    let document = parse_source(source)?;
+    let entry = query::get_entry(&document, path).ok_or_else(|| Error::KeyNotFound(path.to_vec()))?;
+    let span = entry.span();
+
+    let mut end = span.end;
+    let spaces = count_spaces(&source[end..]);
+    let has_comma = source[end + spaces..].starts_with(',');
+    if has_comma {
+        end += spaces + 1;
+    }
+    let has_comment = entry.trailing_comment().is_some();
+    if has_comment {
+        end = line_end(source, end);
+    }
+
+    let start_of_line = line_start(source, span.start);
+    let end_of_line = line_end(source, end);
+    if source[start_of_line..span.start].trim().is_empty() && source[end..end_of_line].trim().is_empty() {
+        let mut start = start_of_line;
+        let mut stop = (end_of_line + 1).min(source.len());
+        // Don't leave two blank lines where the entry used to separate them,
+        // or a blank line at the end of the document.
+        let blank_before = start == 0 || source[..start].ends_with("\n\n");
+        if blank_before && source[stop..].starts_with('\n') {
+            stop += 1;
+        } else if blank_before && stop == source.len() && start > 0 {
+            start -= 1;
+        }
+        return Ok(splice(source, Span::new(start, stop), ""));
+    }
+
+    let mut start = span.start;
+    if has_comment {
+        start = source[..start].trim_end().len();
+    } else if has_comma {
+        end += count_spaces(&source[end..]);
+    } else {
+        let before = source[..start].trim_end();
+        if before.ends_with(',') {
+            start = before.len() - 1;
+        }
+    }
+    Ok(splice(source, Span::new(start, end), ""))
+}
+
+fn insert_into_block(source: &str, block: &KeyValueBlock, span: Span, parent_indent: usize, entry: &KeyValueEntry) -> String {
+    let close = span.end - 1;
+
+    let Some(last) = block.entries.last() else {
+        let child_indent = parent_indent + 2;
+        let rendered = printer::entry_to_source_at(entry, child_indent);
+        let close_line = line_start(source, close);
+        if !source[span.start..span.end].contains('\n') {
+            let text = format!("{{\n{:child_indent$}{}\n{:parent_indent$}}}", "", rendered, "");
+            return splice(source, span, &text);
+        } else if source[close_line..close].trim().is_empty() {
+            let text = format!("{:child_indent$}{}\n", "", rendered);
+            return splice(source, Span::new(close_line, close_line), &text);
+        } else {
+            let text = format!("\n{:child_indent$}{}\n{:parent_indent$}", "", rendered, "");
+            return splice(source, Span::new(close, close), &text);
+        }
+    };
+
+    let after = last.span().end;
+    let spaces = count_spaces(&source[after..close]);
+    let trailing_comma = source[after + spaces..close].starts_with(',');
+
+    // `{ a = 1 }` stays on one line.
+    if !source[span.start..last.span().start].contains('\n') {
+        let rendered = printer::entry_to_source_at(entry, parent_indent);
+        return if trailing_comma {
+            let comma = after + spaces + 1;
+            splice(source, Span::new(comma, comma), &format!(" {},", rendered))
+        } else {
+            splice(source, Span::new(after, after), &format!(", {}", rendered))
+        };
+    }
+
+    let child_indent = indent_of(source, last.span().start);
+    let rendered = printer::entry_to_source_at(entry, child_indent);
+    // Insert after anything else on the last entry's line, such as a comment.
+    let end_of_line = line_end(source, after).min(close);
+    if trailing_comma {
+        let text = format!("\n{:child_indent$}{},", "", rendered);
+        splice(source, Span::new(end_of_line, end_of_line), &text)
+    } else {
+        let text = format!("\n{:child_indent$}{}", "", rendered);
+        let out = splice(source, Span::new(end_of_line, end_of_line), &text);
+        splice(&out, Span::new(after, after), ",")
+    }
+}
+
+fn parse_source(source: &str) -> Result<KeyValueBlock> {
+    let mut diagnostics = vec![];
+    let tokens = lex(source, &mut diagnostics)?;
+    let document = parse(&tokens, &mut diagnostics)?;
+    // Spans from a partially read document can't be trusted for splicing.
+    if !diagnostics.is_empty() {
+        return Err(Error::SyntaxErrors(diagnostics));
+    }
+    Ok(document)
+}
+
+fn splice(source: &str, span: Span, text: &str) -> String {
+    let mut out = String::with_capacity(source.len() + text.len());
+    out.push_str(&source[..span.start]);
+    out.push_str(text);
+    out.push_str(&source[span.end..]);
+    out
+}
+
+fn count_spaces(text: &str) -> usize {
+    text.len() - text.trim_start_matches([' ', '\t']).len()
+}
+
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind('\n').map_or(0, |i| i + 1)
+}
+
+fn line_end(source: &str, offset: usize) -> usize {
+    source[offset..].find('\n').map_or(source.len(), |i| offset + i)
+}
+
+fn indent_of(source: &str, offset: usize) -> usize {
+    count_spaces(&source[line_start(source, offset)..])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keyvalue::BlockValue;
+
+    use super::{insert, remove, set};
+
+    const SOURCE: &str = "\
+# Tables
+Quotes = {
+  id = 1, # primary
+  type = \"Table\",
+  fields = {}
+}
+
+Quotes Machines = { id = 1, table = \"Machines\" }
+";
+
+    fn path(segments: &[&str]) -> Vec<String> {
+        segments.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn set_keeps_surroundings() {
+        let out = set(SOURCE, &path(&["Quotes", "id"]), &BlockValue::Integer(5)).unwrap();
+        assert_eq!(out, SOURCE.replace("id = 1, # primary", "id = 5, # primary"));
+
+        let out = set(SOURCE, &path(&["Quotes Machines", "table"]), &BlockValue::Literal("Quotes".into())).unwrap();
+        assert_eq!(out, SOURCE.replace("table = \"Machines\"", "table = \"Quotes\""));
+    }
+
+    #[test]
+    fn insert_entries() {
+        let out = insert(SOURCE, &path(&["Quotes", "name"]), &BlockValue::Literal("q".into())).unwrap();
+        assert_eq!(out, SOURCE.replace("fields = {}\n", "fields = {},\n  name = \"q\"\n"));
+
+        let out = insert(SOURCE, &path(&["Quotes", "fields", "a"]), &BlockValue::Integer(1)).unwrap();
+        assert_eq!(out, SOURCE.replace("fields = {}", "fields = {\n    a = 1\n  }"));
+
+        let out = insert(SOURCE, &path(&["Quotes Machines", "id2"]), &BlockValue::Integer(2)).unwrap();
+        assert_eq!(out, SOURCE.replace("table = \"Machines\" }", "table = \"Machines\", id2 = 2 }"));
+
+        let out = insert(SOURCE, &path(&["Machines"]), &BlockValue::Integer(2)).unwrap();
+        assert_eq!(out, format!("{SOURCE}\nMachines = 2\n"));
+
+        assert!(insert(SOURCE, &path(&["Quotes", "id"]), &BlockValue::Integer(2)).is_err());
+        assert!(insert(SOURCE, &path(&["Quotes", "id", "x"]), &BlockValue::Integer(2)).is_err());
+    }
+
+    #[test]
+    fn remove_entries() {
+        let out = remove(SOURCE, &path(&["Quotes", "type"])).unwrap();
+        assert_eq!(out, SOURCE.replace("  type = \"Table\",\n", ""));
+
+        let out = remove(SOURCE, &path(&["Quotes Machines", "table"])).unwrap();
+        assert_eq!(out, SOURCE.replace(", table = \"Machines\"", ""));
+
+        let out = remove(SOURCE, &path(&["Quotes Machines", "id"])).unwrap();
+        assert_eq!(out, SOURCE.replace("id = 1, table", "table"));
+
+        let out = remove(SOURCE, &path(&["Quotes Machines"])).unwrap();
+        assert_eq!(out, SOURCE.replace("\nQuotes Machines = { id = 1, table = \"Machines\" }\n", ""));
+
+        let out = remove(SOURCE, &path(&["Quotes", "id"])).unwrap();
+        assert_eq!(out, SOURCE.replace("  id = 1, # primary\n", ""));
+
+        let source = "A = {\n  x = 1, y = 2, # note\n  z = 3 # last\n}\n";
+        assert_eq!(remove(source, &path(&["A", "y"])).unwrap(), "A = {\n  x = 1,\n  z = 3 # last\n}\n");
+        assert_eq!(remove(source, &path(&["A", "z"])).unwrap(), "A = {\n  x = 1, y = 2, # note\n}\n");
+
+        assert!(remove(SOURCE, &path(&["Nope"])).is_err());
+    }
+}
@@ -1,29 +1,175 @@
-use std::num::{ParseFloatError, ParseIntError};
+use std::fmt;
+use std::path::PathBuf;
 
-use crate::token::Token;
+use crate::diagnostic::Diagnostic;
+use crate::token::{SourceLoc, Token, TokenKind};
 
 pub type Result<T> = ::core::result::Result<T, Error>;
 
+/// Problems found while lexing are not errors; they are reported as
+/// [`Diagnostic`]s and lexing carries on.
 #[derive(Debug)]
 pub enum Error {
-    // Lexing
-    Fs(std::io::Error),
-    UnterminatedString,
-    MalformedMacroParameterName,
+    // Parser
+    /// The token that was found, and the kinds of token that would have been
+    /// accepted in its place.
+    UnexpectedToken(Token, Vec<TokenKind>),
+    /// Where the input ended, and what was expected next.
+    UnexpectedEOF(SourceLoc, Vec<TokenKind>),
+    /// The document could only be partially read.
+    SyntaxErrors(Vec<Diagnostic>),
 
-    MalformedFloat(ParseFloatError),
-    MalformedInteger(ParseIntError),
-    MalformedAppend,
+    // Resolution
+    MalformedAppend(SourceLoc),
 
-    // Parser
-    UnexpectedToken(Token),
-    UnexpectedEOF,
+    // Macros
+    UndefinedMacro(String, usize, SourceLoc),
+    UnboundMacroParameter(String, SourceLoc),
+    MacroBodyNotBlock(String, SourceLoc),
+
+    // Editing
+    KeyNotFound(Vec<String>),
+    KeyExists(Vec<String>),
+    NotABlock(Vec<String>, SourceLoc),
+    InvalidPatch(String, SourceLoc),
+
+    // Conversion, where the first field is the key path of the value
+    MissingKey(String, SourceLoc),
+    /// The kind of value that was expected, and the kind that was found.
+    WrongType(String, &'static str, &'static str, SourceLoc),
+    /// The number does not fit the named type.
+    OutOfRange(String, &'static str, SourceLoc),
+
+    // Export
+    /// The document describes something the target format cannot express.
+    CannotExport(String, SourceLoc),
+
+    // Configuration
+    InvalidConfig(String, SourceLoc),
+    InvalidSchema(String, SourceLoc),
+
+    /// An error in a document read from `path`.
+    InFile(PathBuf, Box<Error>),
+}
+
+impl Error {
+    /// Attributes the error to the file it was found in.
+    pub fn in_file(self, path: impl Into<PathBuf>) -> Self {
+        match self {
+            Self::InFile(..) => self,
+            error => Self::InFile(path.into(), Box::new(error)),
+        }
+    }
+
+    /// Where in its document the error happened, if it came from one.
+    pub fn location(&self) -> Option<SourceLoc> {
+        match self {
+            Self::UnexpectedToken(token, _) => Some(token.source_loc),
+            Self::UnexpectedEOF(location, _)
+            | Self::MalformedAppend(location)
+            | Self::UndefinedMacro(_, _, location)
+            | Self::UnboundMacroParameter(_, location)
+            | Self::MacroBodyNotBlock(_, location)
+            | Self::NotABlock(_, location)
+            | Self::InvalidPatch(_, location)
+            | Self::MissingKey(_, location)
+            | Self::WrongType(_, _, _, location)
+            | Self::OutOfRange(_, _, location)
+            | Self::CannotExport(_, location)
+            | Self::InvalidConfig(_, location)
+            | Self::InvalidSchema(_, location) => Some(*location),
+            Self::SyntaxErrors(diagnostics) => diagnostics.first().map(Diagnostic::location),
+            Self::InFile(_, error) => error.location(),
+            Self::KeyNotFound(_) | Self::KeyExists(_) => None,
+        }
+    }
+
+    /// The file the error was found in, if it is known.
+    pub fn file(&self) -> Option<&PathBuf> {
+        match self {
+            Self::InFile(path, _) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Describes the error without its location.
+    pub fn message(&self) -> String {
+        match self {
+            Self::UnexpectedToken(token, expected) => {
+                format!("unexpected `{}`{}", token.token_val, expecting(expected))
+            }
+            Self::UnexpectedEOF(_, expected) => format!("unexpected end of input{}", expecting(expected)),
+            Self::SyntaxErrors(diagnostics) => match diagnostics.as_slice() {
+                [diagnostic] => diagnostic.message().to_string(),
+                diagnostics => format!("{} syntax errors", diagnostics.len()),
+            },
+            Self::MalformedAppend(_) => "`+=` can only add a block to another block".to_string(),
+            Self::UndefinedMacro(name, 1, _) => format!("no macro `{name}` takes 1 argument"),
+            Self::UndefinedMacro(name, arity, _) => format!("no macro `{name}` takes {arity} arguments"),
+            Self::UnboundMacroParameter(param, _) => format!("`${param}` is not a parameter of this macro"),
+            Self::MacroBodyNotBlock(name, _) => format!("the value of `{name}` must be a block to be expanded"),
+            Self::KeyNotFound(path) => format!("key `{}` not found", path.join(".")),
+            Self::KeyExists(path) => format!("key `{}` already exists", path.join(".")),
+            Self::NotABlock(path, _) => format!("`{}` is not a block", path.join(".")),
+            Self::MissingKey(path, _) => format!("missing the required key `{path}`"),
+            Self::WrongType(path, expected, found, _) => format!("`{path}` should be {expected}, found {found}"),
+            Self::OutOfRange(path, kind, _) => format!("`{path}` does not fit in {kind}"),
+            Self::InvalidPatch(message, _)
+            | Self::CannotExport(message, _)
+            | Self::InvalidConfig(message, _)
+            | Self::InvalidSchema(message, _) => message.clone(),
+            Self::InFile(_, error) => error.message(),
+        }
+    }
 }
 
-impl ::core::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{self:?}")
+/// `, expected ...` listing the accepted token kinds, or nothing.
+fn expecting(expected: &[TokenKind]) -> String {
+    let names = expected.iter().map(TokenKind::to_string).collect::<Vec<_>>();
+    match names.as_slice() {
+        [] => String::new(),
+        [one] => format!(", expected {one}"),
+        [first, second] => format!(", expected {first} or {second}"),
+        [rest @ .., last] => format!(", expected one of {} or {last}", rest.join(", ")),
+    }
+}
+
+/// Formats as `path:line:col: message`, leaving out whatever is unknown.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = self.file() {
+            write!(f, "{}:", path.display())?;
+        }
+        match self.location() {
+            Some(location) => write!(f, "{location}: {}", self.message()),
+            None if self.file().is_some() => write!(f, " {}", self.message()),
+            None => write!(f, "{}", self.message()),
+        }
     }
 }
 
 impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use crate::token::{SourceLoc, Token, TokenKind, TokenValue};
+
+    use super::Error;
+
+    #[test]
+    fn messages() {
+        let token = Token::new(TokenValue::Assignment, SourceLoc::new(2, 7));
+        let error = Error::UnexpectedToken(token, vec![TokenKind::Comma, TokenKind::CloseBrace]);
+        assert_eq!(error.to_string(), "2:7: unexpected `=`, expected `,` or `}`");
+        assert_eq!(
+            error.in_file("tables.stoa").to_string(),
+            "tables.stoa:2:7: unexpected `=`, expected `,` or `}`"
+        );
+
+        let error = Error::UnexpectedEOF(SourceLoc::new(1, 4), vec![TokenKind::Identifier]);
+        assert_eq!(error.to_string(), "1:4: unexpected end of input, expected a name");
+
+        let error = Error::KeyNotFound(vec!["Quotes".into(), "id".into()]).in_file("t.stoa");
+        assert_eq!(error.to_string(), "t.stoa: key `Quotes.id` not found");
+    }
+}
@@ -0,0 +1,268 @@
+//! The graph of top-level entries and the references between them, for
+//! drawing. Each entry a reference resolves to is a node, and a table
+//! occurrence is grouped with the table its `table` key names, if that is an
+//! entry of type `Table`.
+
+use std::fmt::Write;
+
+use crate::{
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry},
+    validate::{self, References, REFERENCES, TABLE, TABLE_TYPE},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Node {
+    pub name: String,
+    /// The entry's `type`, if it has one.
+    pub kind: Option<String>,
+}
+
+/// A reference from one entry to another, both given by index into
+/// [`Graph::nodes`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    /// The key path of the reference within `from`.
+    pub label: String,
+}
+
+/// A table and the occurrences of it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Group {
+    pub table: usize,
+    pub occurrences: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    /// References other than the `table` of an occurrence that names a
+    /// table, which is shown by its group instead.
+    pub edges: Vec<Edge>,
+    pub groups: Vec<Group>,
+}
+
+impl Graph {
+    /// Builds the graph of a resolved document, in document order. References
+    /// that do not resolve are left out.
+    pub fn new(document: &KeyValueBlock) -> Self {
+        let index = References::new(document);
+        let entries = document
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry.key(), Key::Name(_)))
+            .collect::<Vec<_>>();
+        let position = |target: &KeyValueEntry| entries.iter().position(|e| e.key() == target.key());
+
+        let mut graph = Graph {
+            nodes: entries
+                .iter()
+                .map(|entry| Node {
+                    name: entry.key().to_string(),
+                    kind: validate::entry_type(entry).map(str::to_string),
+                })
+                .collect(),
+            ..Graph::default()
+        };
+
+        for (from, entry) in entries.iter().enumerate() {
+            let mut references = vec![];
+            for key in REFERENCES {
+                if let Some(reference) = validate::value(entry, key) {
+                    if !matches!(reference.value(), BlockValue::Reference(_)) {
+                        references.push((key.to_string(), reference.value()));
+                    }
+                }
+            }
+            if let BlockValue::Block(block) = entry.value() {
+                paths(block, "", &mut references);
+            }
+
+            for (label, value) in references {
+                let Some(target) = index.resolve(value) else {
+                    continue;
+                };
+                // A path reference points at its top-level entry.
+                let target = match value {
+                    BlockValue::Reference(path) => index.resolve(&BlockValue::Literal(path[0].clone())),
+                    _ => Some(target),
+                };
+                let Some(to) = target.and_then(position) else {
+                    continue;
+                };
+                if label == TABLE && to != from && graph.nodes[to].kind.as_deref() == Some(TABLE_TYPE) {
+                    match graph.groups.iter_mut().find(|g| g.table == to) {
+                        Some(group) => group.occurrences.push(from),
+                        None => graph.groups.push(Group { table: to, occurrences: vec![from] }),
+                    }
+                } else {
+                    graph.edges.push(Edge { from, to, label });
+                }
+            }
+        }
+        graph
+    }
+
+    fn group_of(&self, node: usize) -> Option<&Group> {
+        self.groups.iter().find(|g| g.table == node || g.occurrences.contains(&node))
+    }
+
+    /// Renders the graph in Graphviz's DOT language, with each group as a
+    /// cluster.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph {\n  rankdir=LR;\n  node [shape=box];\n");
+        let node = |i: usize| {
+            let node = &self.nodes[i];
+            let label = match &node.kind {
+                Some(kind) => format!("{}\\n{}", node.name, kind),
+                None => node.name.clone(),
+            };
+            format!("{} [label={}];", dot_id(&node.name), dot_id(&label).replace("\\\\n", "\\n"))
+        };
+
+        for (i, group) in self.groups.iter().enumerate() {
+            let _ = writeln!(out, "  subgraph cluster_{i} {{");
+            let _ = writeln!(out, "    label={};", dot_id(&self.nodes[group.table].name));
+            for member in std::iter::once(group.table).chain(group.occurrences.iter().copied()) {
+                let _ = writeln!(out, "    {}", node(member));
+            }
+            out.push_str("  }\n");
+        }
+        for i in (0..self.nodes.len()).filter(|i| self.group_of(*i).is_none()) {
+            let _ = writeln!(out, "  {}", node(i));
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                out,
+                "  {} -> {} [label={}];",
+                dot_id(&self.nodes[edge.from].name),
+                dot_id(&self.nodes[edge.to].name),
+                dot_id(&edge.label)
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Renders the graph as a Mermaid flowchart, with each group as a
+    /// subgraph. Nodes are numbered, since names may contain spaces.
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart LR\n");
+        let node = |i: usize| {
+            let node = &self.nodes[i];
+            let label = match &node.kind {
+                Some(kind) => format!("{}<br/>{}", node.name, kind),
+                None => node.name.clone(),
+            };
+            format!("n{i}[\"{}\"]", mermaid_text(&label))
+        };
+
+        for (i, group) in self.groups.iter().enumerate() {
+            let _ = writeln!(out, "  subgraph g{i}[\"{}\"]", mermaid_text(&self.nodes[group.table].name));
+            for member in std::iter::once(group.table).chain(group.occurrences.iter().copied()) {
+                let _ = writeln!(out, "    {}", node(member));
+            }
+            out.push_str("  end\n");
+        }
+        for i in (0..self.nodes.len()).filter(|i| self.group_of(*i).is_none()) {
+            let _ = writeln!(out, "  {}", node(i));
+        }
+        for edge in &self.edges {
+            let _ = writeln!(out, "  n{} -->|\"{}\"| n{}", edge.from, mermaid_text(&edge.label), edge.to);
+        }
+        out
+    }
+}
+
+/// `&path` values anywhere in a block, with the key path that holds them.
+fn paths<'a>(block: &'a KeyValueBlock, prefix: &str, references: &mut Vec<(String, &'a BlockValue)>) {
+    for entry in &block.entries {
+        let label = match prefix.is_empty() {
+            true => entry.key().to_string(),
+            false => format!("{prefix}.{}", entry.key()),
+        };
+        match entry.value() {
+            BlockValue::Block(inner) => paths(inner, &label, references),
+            value @ BlockValue::Reference(_) => references.push((label, value)),
+            _ => {}
+        }
+    }
+}
+
+fn dot_id(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn mermaid_text(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::document;
+
+    use super::{Edge, Graph, Group};
+
+    const SOURCE: &str = "\
+Quotes = { id = 1, type = \"Table\", fields = { machine = { link = &Machines.id } } }
+Machines = { id = 2, type = \"Table\" }
+Quotes Machines = { id = 1, type = \"Table Occurrence\", table = \"Machines\" }
+Notes = { table = \"Nowhere\" }
+";
+
+    #[test]
+    fn builds_graph() {
+        let graph = Graph::new(&document(SOURCE));
+        assert_eq!(graph.nodes.len(), 4);
+        assert_eq!(graph.edges, [Edge { from: 0, to: 1, label: "fields.machine.link".to_string() }]);
+        assert_eq!(graph.groups, [Group { table: 1, occurrences: vec![2] }]);
+    }
+
+    #[test]
+    fn groups_only_under_tables() {
+        let text = "A = { type = \"Table\" }\nB = { table = &A }\nC = { table = \"B\" }\n";
+        let graph = Graph::new(&document(text));
+        assert_eq!(graph.groups, [Group { table: 0, occurrences: vec![1] }]);
+        assert_eq!(graph.edges, [Edge { from: 2, to: 1, label: "table".to_string() }]);
+    }
+
+    #[test]
+    fn renders_dot_and_mermaid() {
+        let graph = Graph::new(&document(SOURCE));
+        assert_eq!(
+            graph.to_dot(),
+            "\
+digraph {
+  rankdir=LR;
+  node [shape=box];
+  subgraph cluster_0 {
+    label=\"Machines\";
+    \"Machines\" [label=\"Machines\\nTable\"];
+    \"Quotes Machines\" [label=\"Quotes Machines\\nTable Occurrence\"];
+  }
+  \"Quotes\" [label=\"Quotes\\nTable\"];
+  \"Notes\" [label=\"Notes\"];
+  \"Quotes\" -> \"Machines\" [label=\"fields.machine.link\"];
+}
+"
+        );
+        assert_eq!(
+            graph.to_mermaid(),
+            "\
+flowchart LR
+  subgraph g0[\"Machines\"]
+    n1[\"Machines<br/>Table\"]
+    n2[\"Quotes Machines<br/>Table Occurrence\"]
+  end
+  n0[\"Quotes<br/>Table\"]
+  n3[\"Notes\"]
+  n0 -->|\"fields.machine.link\"| n1
+"
+        );
+    }
+}
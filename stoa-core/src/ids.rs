@@ -0,0 +1,228 @@
+//! Ids for top-level entries that do not write one. Each entry with a
+//! `type` is given the next unused id of that type, and the allocation is
+//! recorded in a lockfile so it never changes:
+//!
+//! ```text
+//! Table = {
+//!   next = 3,
+//!   ids = {
+//!     Machines = 2,
+//!     Quotes = 1
+//!   }
+//! }
+//! ```
+//!
+//! `next` only ever grows, so the id of a removed entry is not given to
+//! another one. Names are kept in order, which keeps the file stable under
+//! version control.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    error::{Error, Result},
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry},
+    printer,
+    token::SourceLoc,
+    validate::{self, ID},
+};
+
+const NEXT: &str = "next";
+const IDS: &str = "ids";
+
+/// The ids allocated so far, by `type` and then by entry name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IdLock {
+    types: BTreeMap<String, Allocation>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Allocation {
+    next: i64,
+    ids: BTreeMap<String, i64>,
+}
+
+/// An id given to an entry by [`IdLock::assign`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub entry: String,
+    pub kind: String,
+    pub id: i64,
+    /// Where the entry is defined.
+    pub location: SourceLoc,
+}
+
+impl IdLock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a lockfile written by [`IdLock::to_source`].
+    pub fn from_block(lock: &KeyValueBlock) -> Result<Self> {
+        let invalid = |message: String, entry: &KeyValueEntry| Error::InvalidConfig(message, entry.location());
+        let mut types = BTreeMap::new();
+        for entry in &lock.entries {
+            let kind = entry.key().to_string();
+            let (BlockValue::Block(_), Some(next), Some(ids)) =
+                (entry.value(), validate::value(entry, NEXT), validate::value(entry, IDS))
+            else {
+                return Err(invalid(format!("`{kind}` must be a block with `{NEXT}` and `{IDS}`"), entry));
+            };
+            let BlockValue::Integer(next) = *next.value() else {
+                return Err(invalid(format!("`{kind}.{NEXT}` must be an integer"), next));
+            };
+            let BlockValue::Block(block) = ids.value() else {
+                return Err(invalid(format!("`{kind}.{IDS}` must be a block"), ids));
+            };
+            let mut allocation = Allocation { next, ids: BTreeMap::new() };
+            for id in &block.entries {
+                let BlockValue::Integer(n) = *id.value() else {
+                    return Err(invalid(format!("the id of `{}` in `{kind}` must be an integer", id.key()), id));
+                };
+                allocation.ids.insert(id.key().to_string(), n);
+            }
+            types.insert(kind, allocation);
+        }
+        Ok(Self { types })
+    }
+
+    pub fn to_source(&self) -> String {
+        let entry = |key: &str, value| KeyValueEntry::new(Key::Name(key.to_string()), SourceLoc::new(0, 0), value);
+        let block = |entries| BlockValue::Block(KeyValueBlock { entries, ..KeyValueBlock::new() });
+        let mut lock = KeyValueBlock::new();
+        for (kind, allocation) in &self.types {
+            let ids = allocation.ids.iter().map(|(name, id)| entry(name, BlockValue::Integer(*id))).collect();
+            lock.add(entry(kind, block(vec![entry(NEXT, BlockValue::Integer(allocation.next)), entry(IDS, block(ids))])));
+        }
+        printer::to_source(&lock)
+    }
+
+    /// Allocates an id to every typed top-level entry of a resolved document
+    /// that has none, either in the document or in the lock. New ids follow
+    /// the largest one already used for the type. Returns only the new ones.
+    pub fn assign(&mut self, document: &KeyValueBlock) -> Vec<Assignment> {
+        let mut largest = HashMap::<&str, i64>::new();
+        for entry in &document.entries {
+            if let (Some(kind), Some(BlockValue::Integer(id))) =
+                (validate::entry_type(entry), validate::value(entry, ID).map(KeyValueEntry::value))
+            {
+                let n = largest.entry(kind).or_insert(*id);
+                *n = (*n).max(*id);
+            }
+        }
+
+        let mut assignments = vec![];
+        for (entry, kind) in unnumbered(document) {
+            let allocation = self.types.entry(kind.to_string()).or_default();
+            let name = entry.key().to_string();
+            if allocation.ids.contains_key(&name) {
+                continue;
+            }
+            let used = allocation.ids.values().chain(largest.get(kind)).max().copied().unwrap_or(0);
+            let id = allocation.next.max(used + 1);
+            allocation.next = id + 1;
+            allocation.ids.insert(name.clone(), id);
+            assignments.push(Assignment { entry: name, kind: kind.to_string(), id, location: entry.location() });
+        }
+        assignments
+    }
+
+    /// Gives every typed top-level entry without an `id` the one allocated
+    /// to it, if any.
+    pub fn apply(&self, document: &mut KeyValueBlock) {
+        for entry in &mut document.entries {
+            let id = match unnumbered_type(entry) {
+                Some(kind) => self.types.get(kind).and_then(|a| a.ids.get(&entry.key().to_string())).copied(),
+                None => None,
+            };
+            if let (Some(id), BlockValue::Block(block)) = (id, entry.value_mut()) {
+                let id = KeyValueEntry::new(Key::Name(ID.to_string()), SourceLoc::new(0, 0), BlockValue::Integer(id));
+                block.entries.insert(0, id);
+            }
+        }
+    }
+}
+
+/// The typed top-level entries that have no `id`, with their type.
+fn unnumbered(document: &KeyValueBlock) -> impl Iterator<Item = (&KeyValueEntry, &str)> {
+    document.entries.iter().filter_map(|entry| Some((entry, unnumbered_type(entry)?)))
+}
+
+fn unnumbered_type(entry: &KeyValueEntry) -> Option<&str> {
+    match (entry.key(), validate::value(entry, ID)) {
+        (Key::Name(_), None) => validate::entry_type(entry),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        keyvalue::BlockValue,
+        testing::document,
+        token::SourceLoc,
+        validate,
+    };
+
+    use super::IdLock;
+
+    fn assigned(lock: &mut IdLock, text: &str) -> Vec<(String, i64)> {
+        lock.assign(&document(text)).into_iter().map(|a| (a.entry, a.id)).collect()
+    }
+
+    #[test]
+    fn allocates_stable_ids() {
+        let mut lock = IdLock::new();
+        let text = "\
+Quotes = { id = 4, type = \"Table\" }
+Machines = { type = \"Table\" }
+Notes = { type = \"Table\" }
+Quotes Machines = { type = \"Table Occurrence\" }
+Settings = { colour = \"red\" }
+";
+        let expected = [("Machines".to_string(), 5), ("Notes".to_string(), 6), ("Quotes Machines".to_string(), 1)];
+        assert_eq!(assigned(&mut lock, text), expected);
+        assert_eq!(assigned(&mut lock, text), []);
+
+        // A removed entry's id is not reused.
+        let text = "Machines = { type = \"Table\" }\nParts = { type = \"Table\" }\n";
+        assert_eq!(assigned(&mut lock, text), [("Parts".to_string(), 7)]);
+
+        let source = lock.to_source();
+        assert_eq!(
+            source,
+            "\
+Table = {
+  next = 8,
+  ids = {
+    Machines = 5,
+    Notes = 6,
+    Parts = 7
+  }
+}
+
+Table Occurrence = {
+  next = 2,
+  ids = {
+    Quotes Machines = 1
+  }
+}
+"
+        );
+        assert_eq!(IdLock::from_block(&document(&source)).unwrap(), lock);
+
+        let mut numbered = document(text);
+        lock.apply(&mut numbered);
+        assert!(validate::ids(&numbered).is_empty());
+        let ids = numbered.entries.iter().map(|e| validate::value(e, "id").unwrap().value().clone());
+        assert_eq!(ids.collect::<Vec<_>>(), [BlockValue::Integer(5), BlockValue::Integer(7)]);
+    }
+
+    #[test]
+    fn rejects_invalid_lockfiles() {
+        let error = IdLock::from_block(&document("Table = { next = 2, ids = { Quotes = \"1\" } }")).unwrap_err();
+        assert!(matches!(error, Error::InvalidConfig(..)));
+        assert_eq!(error.location(), Some(SourceLoc::new(1, 29)));
+        assert_eq!(error.message(), "the id of `Quotes` in `Table` must be an integer");
+    }
+}
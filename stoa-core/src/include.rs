@@ -0,0 +1,259 @@
+//! Documents split across several files. At the top level of a document,
+//! `include "path"` stands for every entry of another document, and
+//! `import "path"` for only its macro definitions, so a library of macros can
+//! be shared without whatever else is defined alongside it. Paths are
+//! relative to the file that names them.
+//!
+//! A [`Loader`] reads each file once, where it is first named, so two files
+//! may import the same library without defining its macros twice.
+//!
+//! Files are read through a [`FileSystem`], so that tests and embedders can
+//! supply them from memory. Each file gets its own [`FileId`], which the
+//! locations of its entries refer to.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCategory},
+    error::Result,
+    keyvalue::{Key, KeyValueBlock, KeyValueEntry},
+    lexer::lex_file,
+    parser::parse,
+    token::FileId,
+};
+
+/// Where included files are read from.
+pub trait FileSystem {
+    fn read(&self, path: &Path) -> io::Result<String>;
+}
+
+/// The files on disk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+/// Files held in memory, by path.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file(mut self, path: impl Into<PathBuf>, text: impl Into<String>) -> Self {
+        self.files.insert(normalise(&path.into()), text.into());
+        self
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.files.get(&normalise(path)).cloned().ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+}
+
+/// A file read by a [`Loader`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// Reads documents along with every file they include or import.
+#[derive(Debug)]
+pub struct Loader<F> {
+    fs: F,
+    files: Vec<SourceFile>,
+    /// The files loaded so far, by normalised path.
+    loaded: HashSet<PathBuf>,
+}
+
+impl<F: FileSystem> Loader<F> {
+    pub fn new(fs: F) -> Self {
+        Self { fs, files: vec![], loaded: HashSet::new() }
+    }
+
+    /// Adds a document that was read some other way, such as from standard
+    /// input, and returns its id. Files are numbered from 0 in the order
+    /// they are added or included.
+    pub fn add(&mut self, path: impl Into<PathBuf>, text: impl Into<String>) -> FileId {
+        self.files.push(SourceFile { path: path.into(), text: text.into() });
+        (self.files.len() - 1) as FileId
+    }
+
+    /// Reads a document from the file system and adds it.
+    pub fn read(&mut self, path: impl Into<PathBuf>) -> io::Result<FileId> {
+        let path = path.into();
+        let text = self.fs.read(&path)?;
+        Ok(self.add(path, text))
+    }
+
+    /// Every file added or included so far, indexed by id.
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Parses an added document, replacing each directive with the entries
+    /// it names, recursively. Syntax errors are recorded in `diagnostics` as
+    /// [`parse`] does, along with files that cannot be read or that include
+    /// themselves, whose directives are skipped. So are directives naming a
+    /// file this loader already loaded, and a document that was already
+    /// loaded is empty.
+    pub fn load(&mut self, file: FileId, diagnostics: &mut Vec<Diagnostic>) -> Result<KeyValueBlock> {
+        let path = normalise(&self.files[file as usize].path);
+        if !self.loaded.insert(path.clone()) {
+            return Ok(KeyValueBlock::new());
+        }
+        self.expand(file, &mut vec![path], diagnostics)
+    }
+
+    /// `chain` holds the files being loaded, from the outermost to `file`.
+    fn expand(&mut self, file: FileId, chain: &mut Vec<PathBuf>, diagnostics: &mut Vec<Diagnostic>) -> Result<KeyValueBlock> {
+        let tokens = lex_file(&self.files[file as usize].text, file, diagnostics)?;
+        let document = parse(&tokens, diagnostics)?;
+
+        let mut loaded = KeyValueBlock { comments: document.comments, ..KeyValueBlock::new() };
+        for entry in document.entries {
+            let (Key::Include(name) | Key::Import(name)) = entry.key() else {
+                loaded.add(entry);
+                continue;
+            };
+            let directory = chain.last().and_then(|path| path.parent()).unwrap_or(Path::new(""));
+            let path = normalise(&directory.join(name));
+            if chain.contains(&path) {
+                diagnostics.push(cycle(&entry, chain, &path));
+                continue;
+            }
+            if self.loaded.contains(&path) {
+                continue;
+            }
+            let text = match self.fs.read(&path) {
+                Ok(text) => text,
+                Err(e) => {
+                    let diagnostic = Diagnostic::new(DiagnosticCategory::CannotInclude, entry.location(), name.clone())
+                        .with_span(entry.span())
+                        .with_note(format!("{}: {e}", path.display()));
+                    diagnostics.push(diagnostic);
+                    continue;
+                }
+            };
+
+            let id = self.add(path.clone(), text);
+            self.loaded.insert(path.clone());
+            chain.push(path);
+            let included = self.expand(id, chain, diagnostics)?;
+            chain.pop();
+            match entry.key() {
+                Key::Include(_) => loaded.entries.extend(included.entries),
+                _ => loaded.entries.extend(included.entries.into_iter().filter(|e| matches!(e.key(), Key::MacroSignature { .. }))),
+            }
+        }
+        Ok(loaded)
+    }
+}
+
+fn cycle(entry: &KeyValueEntry, chain: &[PathBuf], path: &Path) -> Diagnostic {
+    let start = chain.iter().position(|p| p == path).unwrap_or(0);
+    let names = chain[start..].iter().chain([&path.to_path_buf()]).map(|p| p.display().to_string()).collect::<Vec<_>>();
+    Diagnostic::new(DiagnosticCategory::IncludeCycle, entry.location(), String::new())
+        .with_span(entry.span())
+        .with_message(format!("`{}` includes itself", path.display()))
+        .with_note(format!("through {}", names.join(" -> ")))
+}
+
+/// `path` with `.` and `..` folded away without consulting the file system,
+/// so that a file is known by the same path however it is reached.
+fn normalise(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normal.components().next_back(), Some(Component::Normal(_))) => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        diagnostic::DiagnosticCategory,
+        r#macro::expand_macros,
+        token::SourceLoc,
+    };
+
+    use super::{Loader, MemoryFileSystem};
+
+    fn files() -> MemoryFileSystem {
+        MemoryFileSystem::new()
+            .with_file("macros/common.stoa", "macro table($id, $name) = { $name = { id = $id } }\nExample = 1\n")
+            .with_file("tables/quotes.stoa", "include \"./lines.stoa\"\n@table(1, Quotes) = {}\n")
+            .with_file("tables/lines.stoa", "import \"../macros/common.stoa\"\n@table(2, Lines) = {}\n")
+    }
+
+    #[test]
+    fn includes_and_imports_files() {
+        let mut loader = Loader::new(files());
+        let text = "import \"macros/common.stoa\"\ninclude \"tables/quotes.stoa\"\ninclude \"tables/lines.stoa\"\nimport = 3\n";
+        let root = loader.add("main.stoa", text);
+        let mut diagnostics = vec![];
+        let mut document = loader.load(root, &mut diagnostics).unwrap();
+        assert!(diagnostics.is_empty());
+
+        let paths = loader.files().iter().map(|f| f.path.display().to_string()).collect::<Vec<_>>();
+        assert_eq!(paths, ["main.stoa", "macros/common.stoa", "tables/quotes.stoa", "tables/lines.stoa"]);
+        let locations = document.entries.iter().map(|e| e.location()).collect::<Vec<_>>();
+        assert_eq!(
+            locations,
+            [
+                SourceLoc::new(1, 1).with_file(1),
+                SourceLoc::new(2, 1).with_file(3),
+                SourceLoc::new(2, 1).with_file(2),
+                SourceLoc::new(4, 1),
+            ]
+        );
+
+        expand_macros(&mut document).unwrap();
+        let keys = document.entries.iter().map(|e| e.key().to_string()).collect::<Vec<_>>();
+        assert_eq!(keys, ["Lines", "Quotes", "import"]);
+
+        let lines = loader.read("tables/./lines.stoa").unwrap();
+        assert!(loader.load(lines, &mut diagnostics).unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn reports_cycles_and_missing_files() {
+        let fs = files()
+            .with_file("a.stoa", "include \"tables/../b.stoa\"\nA = 1\n")
+            .with_file("b.stoa", "include \"a.stoa\"\ninclude \"missing.stoa\"\nB = 2\n");
+        let mut loader = Loader::new(fs);
+        let root = loader.read("a.stoa").unwrap();
+        let mut diagnostics = vec![];
+        let document = loader.load(root, &mut diagnostics).unwrap();
+        let keys = document.entries.iter().map(|e| e.key().to_string()).collect::<Vec<_>>();
+        assert_eq!(keys, ["B", "A"]);
+
+        assert_eq!(diagnostics[0].category(), DiagnosticCategory::IncludeCycle);
+        assert_eq!(diagnostics[0].location(), SourceLoc::new(1, 1).with_file(1));
+        assert_eq!(diagnostics[0].message(), "`a.stoa` includes itself");
+        assert_eq!(diagnostics[0].notes(), ["through a.stoa -> b.stoa -> a.stoa"]);
+        assert_eq!(diagnostics[1].category(), DiagnosticCategory::CannotInclude);
+        assert_eq!(diagnostics[1].message(), "cannot include `missing.stoa`");
+        assert_eq!(diagnostics[1].location(), SourceLoc::new(2, 1).with_file(1));
+    }
+}
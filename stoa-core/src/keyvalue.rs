@@ -1,18 +1,31 @@
 
-use crate::token::SourceLoc;
+use crate::diagnostic::{Diagnostic, DiagnosticCategory, Severity};
+use crate::error::{Error, Result};
+use crate::token::{SourceLoc, Span};
+use std::collections::HashMap;
 use std::fmt::{self, Write};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value"))]
 pub enum Key {
     Name(String),
+    /// A name with no value, such as `required`, which stands for itself.
+    Bare(String),
     MacroValue(String),
-    MacroSignature { name: String, args: Vec::<String> }
+    MacroSignature { name: String, args: Vec::<String> },
+    MacroCall { name: String, args: Vec::<String> },
+    /// `include "path"`, which stands for the entries of another document.
+    Include(String),
+    /// `import "path"`, which stands for the macros of another document.
+    Import(String),
 }
 
 impl Key {
     fn pretty_string(&self) -> String {
         match self {
             Key::Name(name) => format!("Name(\"{}\")", name),
+            Key::Bare(name) => format!("Bare(\"{}\")", name),
             Key::MacroValue(val) => format!("MacroValue({})", val),
             Key::MacroSignature { name, args } => {
                 format!(
@@ -20,19 +33,51 @@ impl Key {
                     name, args
                 )
             }
+            Key::MacroCall { name, args } => {
+                format!(
+                    "MacroCall {{ name: \"{}\", args: {:?} }}",
+                    name, args
+                )
+            }
+            Key::Include(path) => format!("Include(\"{}\")", path),
+            Key::Import(path) => format!("Import(\"{}\")", path),
+        }
+    }
+}
+
+/// Formats the key as it would be written in stoa source.
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Name(name) | Key::Bare(name) => write!(f, "{}", name),
+            Key::MacroValue(val) => write!(f, "${}", val),
+            Key::MacroSignature { name, args } => {
+                let args = args.iter()
+                    .map(|a| format!("${}", a))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "macro {}({})", name, args)
+            }
+            Key::MacroCall { name, args } => write!(f, "@{}({})", name, args.join(", ")),
+            Key::Include(path) => write!(f, "include \"{}\"", path),
+            Key::Import(path) => write!(f, "import \"{}\"", path),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct KeyValueBlock {
-    pub entries: Vec<KeyValueEntry>
+    pub entries: Vec<KeyValueEntry>,
+    /// Comments after the last entry, without the leading `#`.
+    pub comments: Vec<String>,
 }
 
 impl KeyValueBlock {
     pub fn new() -> Self {
         Self {
-            entries: vec![]
+            entries: vec![],
+            comments: vec![],
         }
     }
     pub fn add(&mut self, entry: KeyValueEntry) {
@@ -44,6 +89,85 @@ impl KeyValueBlock {
             .find_map(|e| (e.key == key).then_some(&e.value))
     }
 
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut BlockValue> {
+        self.entries.iter_mut()
+            .find_map(|e| (e.key == key).then_some(&mut e.value))
+    }
+
+    /// Folds every `key += { ... }` entry into the closest preceding
+    /// definition of the same key, recursively. Only blocks can be appended
+    /// to; appending to a key that has not been defined yet defines it.
+    pub fn resolve_appends(&mut self) -> Result<()> {
+        let entries = std::mem::take(&mut self.entries);
+        for mut entry in entries {
+            let target = match entry.operator {
+                Operator::Append => self.entries.iter_mut().rev().find(|e| e.key == entry.key),
+                Operator::Assign => None,
+            };
+
+            match target {
+                Some(target) => match (&mut target.value, entry.value) {
+                    (BlockValue::Block(existing), BlockValue::Block(addition)) => {
+                        existing.entries.extend(addition.entries);
+                        existing.comments.extend(addition.comments);
+                        existing.resolve_appends()?;
+                    }
+                    _ => return Err(Error::MalformedAppend(entry.location)),
+                },
+                None => {
+                    entry.operator = Operator::Assign;
+                    if let BlockValue::Block(block) = &mut entry.value {
+                        block.resolve_appends()?;
+                    }
+                    self.entries.push(entry);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports every key that is assigned more than once in the same block,
+    /// recursively, and resolves each according to `policy`. Each diagnostic
+    /// points at the repeated definition and relates it to the first.
+    pub fn resolve_duplicates(&mut self, policy: DuplicatePolicy) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        self.resolve_duplicates_into(policy, &mut diagnostics);
+        diagnostics
+    }
+
+    fn resolve_duplicates_into(&mut self, policy: DuplicatePolicy, diagnostics: &mut Vec<Diagnostic>) {
+        let entries = std::mem::take(&mut self.entries);
+        let mut first = HashMap::new();
+        for entry in entries {
+            let Key::Name(name) = &entry.key else {
+                self.entries.push(entry);
+                continue;
+            };
+            let Some(&index) = first.get(name) else {
+                first.insert(name.clone(), self.entries.len());
+                self.entries.push(entry);
+                continue;
+            };
+
+            let existing = &mut self.entries[index];
+            diagnostics.push(policy.report(name, existing, &entry));
+            match (policy, &mut existing.value, entry.value) {
+                (DuplicatePolicy::Error, _, value) => self.entries.push(KeyValueEntry { value, ..entry }),
+                (DuplicatePolicy::Merge, BlockValue::Block(existing), BlockValue::Block(addition)) => {
+                    existing.entries.extend(addition.entries);
+                    existing.comments.extend(addition.comments);
+                }
+                (_, _, value) => *existing = KeyValueEntry { value, ..entry },
+            }
+        }
+
+        for entry in &mut self.entries {
+            if let BlockValue::Block(block) = &mut entry.value {
+                block.resolve_duplicates_into(policy, diagnostics);
+            }
+        }
+    }
+
     pub fn pretty_string(&self) -> String {
         let mut out = String::new();
         let _ = self.pretty_fmt(&mut out, 0);
@@ -58,11 +182,55 @@ impl KeyValueBlock {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// What happens to a key that is assigned more than once in the same block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// The document is rejected.
+    Error,
+    /// The last value replaces the others, in the place of the first.
+    #[default]
+    LastWins,
+    /// Blocks are merged into the first definition, as if `+=` had been used.
+    /// Any other value replaces the earlier one.
+    Merge,
+}
+
+impl DuplicatePolicy {
+    fn report(&self, name: &str, first: &KeyValueEntry, repeated: &KeyValueEntry) -> Diagnostic {
+        let diagnostic = Diagnostic::new(DiagnosticCategory::DuplicateKey, repeated.location, String::new())
+            .with_span(repeated.span)
+            .with_message(format!("`{name}` is defined more than once"))
+            .with_related(first.location, first.span, "first defined here");
+        match self {
+            Self::Error => diagnostic
+                .with_severity(Severity::Error)
+                .with_help("use `+=` to add to the first definition"),
+            Self::LastWins => diagnostic.with_note("only the last value is kept"),
+            Self::Merge => diagnostic.with_note("the definitions are merged"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Operator {
+    /// `key = value`
+    Assign,
+    /// `key += { ... }`
+    Append,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct KeyValueEntry {
     key: Key,
     value: BlockValue,
     location: SourceLoc,
+    operator: Operator,
+    span: Span,
+    value_span: Option<Span>,
+    comments: Vec<String>,
+    trailing_comment: Option<String>,
 }
 
 impl KeyValueEntry {
@@ -71,11 +239,98 @@ impl KeyValueEntry {
             key,
             location,
             value,
+            operator: Operator::Assign,
+            span: Span::default(),
+            value_span: None,
+            comments: vec![],
+            trailing_comment: None,
         }
     }
+
+    pub fn new_append(key: Key, location: SourceLoc, value: BlockValue) -> Self {
+        Self {
+            key,
+            location,
+            value,
+            operator: Operator::Append,
+            span: Span::default(),
+            value_span: None,
+            comments: vec![],
+            trailing_comment: None,
+        }
+    }
+
+    /// Records where the entry, and its value if it has one, were found in
+    /// the source text.
+    pub fn with_span(mut self, span: Span, value_span: Option<Span>) -> Self {
+        self.span = span;
+        self.value_span = value_span;
+        self
+    }
+
+    pub fn with_comments(mut self, comments: Vec<String>) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    pub fn with_trailing_comment(mut self, comment: Option<String>) -> Self {
+        self.trailing_comment = comment;
+        self
+    }
+
+    /// Moves the entry to another place in the source, such as the macro call
+    /// that produced it. The value span no longer applies and is dropped.
+    pub fn relocated(mut self, location: SourceLoc, span: Span) -> Self {
+        self.location = location;
+        self.span = span;
+        self.value_span = None;
+        self
+    }
+
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    pub fn value(&self) -> &BlockValue {
+        &self.value
+    }
+
+    pub fn value_mut(&mut self) -> &mut BlockValue {
+        &mut self.value
+    }
+
+    pub fn location(&self) -> SourceLoc {
+        self.location
+    }
+
+    pub fn operator(&self) -> Operator {
+        self.operator
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn value_span(&self) -> Option<Span> {
+        self.value_span
+    }
+
+    /// Comments on the lines before the entry, without the leading `#`.
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// A comment after the entry on its last line, without the leading `#`.
+    pub fn trailing_comment(&self) -> Option<&str> {
+        self.trailing_comment.as_deref()
+    }
+
     fn pretty_fmt(&self, f: &mut String, indent: usize) -> fmt::Result {
         writeln!(f, "{:indent$}KeyValueEntry {{", "", indent = indent)?;
         writeln!(f, "{:indent$}key: {},", "  ", self.key.pretty_string(), indent = indent + 2)?;
+        if self.operator == Operator::Append {
+            writeln!(f, "{:indent$}operator: Append,", "", indent = indent + 2)?;
+        }
         writeln!(f, "{:indent$}value:", "", indent = indent + 2)?;
         self.value.pretty_fmt(f, indent + 4)?;
         // writeln!(f, "{:indent$}location: {:?},", "", self.location, indent = indent + 2)?;
@@ -83,27 +338,57 @@ impl KeyValueEntry {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value"))]
 pub enum BlockValue {
     Literal(String),
+    Integer(i64),
+    Float(f64),
     Expression(String),
     Block(KeyValueBlock),
     MacroValue(String),
+    /// `&Quotes.fields.id`: the path of keys to another entry, resolved once
+    /// macros are expanded.
+    Reference(Vec<String>),
     Empty,
 }
 
 impl BlockValue {
+    /// The kind of value, as it reads in a message: `a string`, `a block`...
+    pub fn describe(&self) -> &'static str {
+        match self {
+            BlockValue::Literal(_) => "a string",
+            BlockValue::Integer(_) => "an integer",
+            BlockValue::Float(_) => "a float",
+            BlockValue::Expression(_) => "an expression",
+            BlockValue::Block(_) => "a block",
+            BlockValue::MacroValue(_) => "a macro parameter",
+            BlockValue::Reference(_) => "a reference",
+            BlockValue::Empty => "a bare name",
+        }
+    }
+
     fn pretty_fmt(&self, f: &mut String, indent: usize) -> fmt::Result {
         match self {
             BlockValue::Literal(s) => {
                 writeln!(f, "{:indent$}Literal(\"{}\"),", "", s, indent = indent)
             }
+            BlockValue::Integer(n) => {
+                writeln!(f, "{:indent$}Integer({}),", "", n, indent = indent)
+            }
+            BlockValue::Float(n) => {
+                writeln!(f, "{:indent$}Float({:?}),", "", n, indent = indent)
+            }
             BlockValue::Expression(s) => {
                 writeln!(f, "{:indent$}Expression(\"{}\"),", "", s, indent = indent)
             }
             BlockValue::MacroValue(s) => {
                 writeln!(f, "{:indent$}MacroValue(\"{}\"),", "", s, indent = indent)
             }
+            BlockValue::Reference(path) => {
+                writeln!(f, "{:indent$}Reference({:?}),", "", path, indent = indent)
+            }
             BlockValue::Empty => {
                 writeln!(f, "{:indent$}Empty,", "", indent = indent)
             }
@@ -114,26 +399,45 @@ impl BlockValue {
             }
         }
     }
-
-    fn short_debug(&self) -> String {
-        match self {
-            BlockValue::Literal(s) => format!("Literal(\"{}\")", s),
-            BlockValue::Expression(s) => format!("Expression(\"{}\")", s),
-            BlockValue::MacroValue(s) => format!("MacroValue(\"{}\")", s),
-            BlockValue::Empty => "Empty".to_string(),
-            BlockValue::Block(_) => "Block(...)".to_string(),
-        }
-    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::KeyValueBlock;
+    use crate::testing::document;
+
+    use super::{BlockValue, Key, KeyValueBlock};
 
     #[test]
     fn blocks() {
         let _ = KeyValueBlock {
-            entries: vec![]
+            entries: vec![],
+            comments: vec![],
         };
     }
+
+    #[test]
+    fn appends_fold_into_definition() {
+        let mut store = document(
+            "Quotes = { id = 1, fields = { a = 1 } } \
+             Machines = { id = 2 } \
+             Quotes += { fields += { b = 2 }, type = \"Table\" }",
+        );
+        store.resolve_appends().unwrap();
+
+        assert_eq!(store.entries.len(), 2);
+        let Some(BlockValue::Block(quotes)) = store.get(Key::Name("Quotes".to_string())) else {
+            panic!("Quotes is not a block");
+        };
+        assert_eq!(quotes.entries.len(), 3);
+        let Some(BlockValue::Block(fields)) = quotes.get(Key::Name("fields".to_string())) else {
+            panic!("fields is not a block");
+        };
+        assert_eq!(fields.get(Key::Name("b".to_string())), Some(&BlockValue::Integer(2)));
+    }
+
+    #[test]
+    fn append_to_value_fails() {
+        let mut store = document("number = 32 number += { nested = 32 }");
+        assert!(store.resolve_appends().is_err());
+    }
 }
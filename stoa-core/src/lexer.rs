@@ -1,24 +1,25 @@
 use std::iter::Peekable;
 
 use crate::{
-    diagnostic::Diagnostic,
-    error::{Error, Result},
-    token::{match_keyword, SourceLoc, Token, TokenValue, KEYWORD_MAP},
+    diagnostic::{Diagnostic, DiagnosticCategory},
+    error::Result,
+    token::{match_keyword, FileId, SourceLoc, Span, Token, TokenValue},
 };
 
 struct LexIter<'a> {
-    input: &'a str,
     line: u32,
     column: u32,
+    /// Byte offset of the next character.
+    offset: usize,
     chars: Peekable<std::str::Chars<'a>>,
 }
 
 impl<'a> LexIter<'a> {
     pub fn new(input: &'a str) -> Self {
         Self {
-            input,
             line: 1,
             column: 0,
+            offset: 0,
             chars: input.chars().peekable(),
         }
     }
@@ -28,7 +29,7 @@ impl<'a> LexIter<'a> {
             let mut column = self.column;
             if *c == '\n' {
                 line += 1;
-                column = 1;
+                column = 0;
             } else {
                 column += 1;
             }
@@ -44,9 +45,10 @@ impl<'a> Iterator for LexIter<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(c) = self.chars.next() {
+            self.offset += c.len_utf8();
             if c == '\n' {
                 self.line += 1;
-                self.column = 1;
+                self.column = 0;
             } else {
                 self.column += 1;
             }
@@ -57,17 +59,28 @@ impl<'a> Iterator for LexIter<'a> {
     }
 }
 
+/// Splits `text` into tokens. Malformed input is reported in `diagnostics`
+/// and skipped, so lexing always runs to the end of the text.
 pub fn lex(text: &str, diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<Token>> {
+    lex_file(text, 0, diagnostics)
+}
+
+/// Like [`lex`], locating every token in `file`.
+pub fn lex_file(text: &str, file: FileId, diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<Token>> {
     let mut tokens = Vec::<Token>::new();
     let mut iter = LexIter::new(text);
     while let Some((c, line, col)) = iter.next() {
+        let start = iter.offset - c.len_utf8();
+        let loc = SourceLoc::new(line, col).with_file(file);
+        let pushed = tokens.len();
+        let reported = diagnostics.len();
         match c {
-            c if c.is_alphabetic() => {
+            c if is_identifier_start(c) => {
                 let mut buffer = String::new();
                 buffer.push(c);
 
                 while let Some(&next_c) = iter.chars.peek() {
-                    if next_c.is_alphanumeric() {
+                    if is_identifier_continue(next_c) {
                         if let Some((c, _, _)) = iter.next() {
                             buffer.push(c);
                         }
@@ -76,15 +89,10 @@ pub fn lex(text: &str, diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<Token>>
                     }
                 }
 
-                if !buffer.is_empty() {
-                    if let Some(keyword) = match_keyword(&buffer) {
-                        tokens.push(Token::new(keyword, SourceLoc::new(line, col)));
-                    } else {
-                        tokens.push(Token::new(
-                            TokenValue::Identifier(buffer.trim().to_string()),
-                            SourceLoc::new(line, col),
-                        ));
-                    }
+                if let Some(keyword) = match_keyword(&buffer) {
+                    tokens.push(Token::new(keyword, loc));
+                } else {
+                    tokens.push(Token::new(TokenValue::Identifier(buffer), loc));
                 }
             }
             d if d.is_numeric() => {
@@ -96,124 +104,142 @@ pub fn lex(text: &str, diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<Token>>
                     if next_c.is_ascii_digit() {
                         buffer.push(next_c);
                         iter.next();
+                    } else if next_c == '.' {
+                        is_float = true;
+                        buffer.push(next_c);
+                        iter.next();
                     } else {
-                        if next_c == '.' {
-                            // If we already have a dot
-                            is_float = true;
-                            buffer.push(next_c);
-                            iter.next();
-                        } else {
-                            break;
-                        }
+                        break;
                     }
                 }
 
                 let value = if is_float {
-                    TokenValue::FloatLiteral(buffer.parse::<f64>().map_err(Error::MalformedFloat)?)
+                    buffer.parse::<f64>().map(TokenValue::FloatLiteral).ok()
                 } else {
-                    TokenValue::IntegerLiteral(
-                        buffer.parse::<i64>().map_err(Error::MalformedInteger)?,
-                    )
+                    buffer.parse::<i64>().map(TokenValue::IntegerLiteral).ok()
                 };
 
-                tokens.push(Token::new(value, SourceLoc::new(line, col)));
+                match value {
+                    Some(value) => tokens.push(Token::new(value, loc)),
+                    None => diagnostics.push(Diagnostic::new(DiagnosticCategory::MalformedNumber, loc, buffer)),
+                }
             }
             '"' => {
                 // TODO: Proper string parsing with escapes
                 let mut buffer = String::new();
+                let mut terminated = false;
                 while let Some(next_c) = iter.chars.peek() {
                     if *next_c == '"' {
                         iter.next();
+                        terminated = true;
                         break;
                     }
                     buffer.push(*next_c);
                     iter.next();
                 }
-                tokens.push(Token::new(
-                    TokenValue::String(buffer),
-                    SourceLoc::new(line, col),
-                ));
+                if terminated {
+                    tokens.push(Token::new(TokenValue::String(buffer), loc));
+                } else {
+                    diagnostics.push(Diagnostic::new(DiagnosticCategory::UnterminatedString, loc, String::new()));
+                }
             }
             '+' => {
-                let Some((c, _, _)) = iter.peek() else {
-                    return Err(Error::UnexpectedEOF);
-                };
-
-                if !(c == '=') {
-                    return Err(Error::MalformedAppend);
+                if matches!(iter.peek(), Some(('=', _, _))) {
+                    iter.next();
+                    tokens.push(Token::new(TokenValue::Append, loc))
+                } else {
+                    diagnostics.push(
+                        Diagnostic::new(DiagnosticCategory::MalformedAppend, loc, String::new())
+                            .with_help("use `+=` to append to a block"),
+                    );
                 }
-
-                tokens.push(Token::new(TokenValue::Append, SourceLoc::new(line, col)))
             }
-            '=' => tokens.push(Token::new(
-                TokenValue::Assignment,
-                SourceLoc::new(line, col),
-            )),
-            '{' => tokens.push(Token::new(TokenValue::OpenBrace, SourceLoc::new(line, col))),
-            '}' => tokens.push(Token::new(
-                TokenValue::CloseBrace,
-                SourceLoc::new(line, col),
-            )),
-            ',' => tokens.push(Token::new(TokenValue::Comma, SourceLoc::new(line, col))),
-            '(' => tokens.push(Token::new(TokenValue::OpenParen, SourceLoc::new(line, col))),
-            ')' => tokens.push(Token::new(
-                TokenValue::CloseParen,
-                SourceLoc::new(line, col),
-            )),
-            '$' => {
+            '=' => tokens.push(Token::new(TokenValue::Assignment, loc)),
+            '{' => tokens.push(Token::new(TokenValue::OpenBrace, loc)),
+            '}' => tokens.push(Token::new(TokenValue::CloseBrace, loc)),
+            ',' => tokens.push(Token::new(TokenValue::Comma, loc)),
+            '(' => tokens.push(Token::new(TokenValue::OpenParen, loc)),
+            ')' => tokens.push(Token::new(TokenValue::CloseParen, loc)),
+            '$' | '@' => {
                 let mut buffer = String::new();
-                let Some(next_c) = iter.chars.peek() else {
-                    return Err(Error::UnexpectedEOF);
-                };
-
-                if !next_c.is_alphabetic() {
-                    return Err(Error::MalformedMacroParameterName);
-                }
-
                 while let Some(next_c) = iter.chars.peek() {
-                    if !next_c.is_alphanumeric() {
+                    if !is_identifier_continue(*next_c) {
                         break;
                     }
                     buffer.push(*next_c);
                     iter.next();
                 }
 
-                tokens.push(Token::new(
-                    TokenValue::MacroParameter(buffer),
-                    SourceLoc::new(line, col),
-                ));
+                if !buffer.starts_with(is_identifier_start) {
+                    diagnostics.push(Diagnostic::new(DiagnosticCategory::MalformedMacroName, loc, c.to_string()));
+                } else if c == '$' {
+                    tokens.push(Token::new(TokenValue::MacroParameter(buffer), loc));
+                } else {
+                    tokens.push(Token::new(TokenValue::MacroCall(buffer), loc));
+                }
             }
-            '@' => {
+            '&' => {
+                // Keys may be several words, so spaces are part of the path
+                // up to the end of the line.
                 let mut buffer = String::new();
-                let Some(next_c) = iter.chars.peek() else {
-                    return Err(Error::UnexpectedEOF);
-                };
-
-                if !next_c.is_alphabetic() {
-                    return Err(Error::MalformedMacroParameterName);
+                while let Some(next_c) = iter.chars.peek() {
+                    if !(is_identifier_continue(*next_c) || *next_c == '.' || *next_c == ' ') {
+                        break;
+                    }
+                    buffer.push(*next_c);
+                    iter.next();
                 }
+                let trimmed = buffer.trim_end();
+                // Only the trailing spaces are given back to the span.
+                iter.offset -= buffer.len() - trimmed.len();
 
+                let path = trimmed.split('.').map(|segment| segment.trim().to_string()).collect::<Vec<_>>();
+                if path.iter().all(|segment| segment.starts_with(is_identifier_start)) {
+                    tokens.push(Token::new(TokenValue::Reference(path), loc));
+                } else {
+                    diagnostics.push(Diagnostic::new(DiagnosticCategory::MalformedReference, loc, c.to_string()));
+                }
+            }
+            '#' => {
+                let mut buffer = String::new();
                 while let Some(next_c) = iter.chars.peek() {
-                    if !next_c.is_alphanumeric() {
+                    if *next_c == '\n' {
                         break;
                     }
                     buffer.push(*next_c);
                     iter.next();
                 }
                 tokens.push(Token::new(
-                    TokenValue::MacroCall(buffer),
-                    SourceLoc::new(line, col),
+                    TokenValue::Comment(buffer.trim_end().to_string()),
+                    loc,
                 ));
             }
-            _ => {}
+            c if c.is_whitespace() => {}
+            c => diagnostics.push(Diagnostic::new(DiagnosticCategory::UnexpectedCharacter, loc, c.to_string())),
+        }
+
+        let span = Span::new(start, iter.offset);
+        if tokens.len() > pushed {
+            tokens[pushed].span = span;
+        }
+        for diagnostic in &mut diagnostics[reported..] {
+            *diagnostic = diagnostic.clone().with_span(span);
         }
     }
     Ok(tokens)
 }
 
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_identifier_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::token::TokenValue;
+    use crate::{diagnostic::DiagnosticCategory, token::{SourceLoc, TokenValue}};
 
     use super::lex;
 
@@ -293,4 +319,67 @@ mod tests {
         assert_eq!(tokens[6].token_val, TokenValue::OpenBrace);
         assert_eq!(tokens[7].token_val, TokenValue::CloseBrace);
     }
+
+    #[test]
+    fn comments_and_spans() {
+        let text = "# a table\nQuotes = {} # done";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        assert_eq!(tokens[0].token_val, TokenValue::Comment(" a table".to_string()));
+        assert_eq!(tokens[1].token_val, TokenValue::Identifier("Quotes".to_string()));
+        assert_eq!(&text[tokens[1].span.start..tokens[1].span.end], "Quotes");
+        assert_eq!(&text[tokens[4].span.start..tokens[4].span.end], "}");
+        assert_eq!(tokens[5].token_val, TokenValue::Comment(" done".to_string()));
+    }
+
+    #[test]
+    fn columns_on_later_lines() {
+        let text = "a = 1\n  b = 2";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        assert_eq!(tokens[3].source_loc, SourceLoc::new(2, 3));
+    }
+
+    #[test]
+    fn malformed_input_is_reported() {
+        let text = "constant_number = 32;\nx + 1\nname = \"open";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        assert_eq!(tokens[0].token_val, TokenValue::Identifier("constant_number".to_string()));
+        let categories = diags.iter().map(|d| d.category()).collect::<Vec<_>>();
+        assert_eq!(
+            categories,
+            [
+                DiagnosticCategory::UnexpectedCharacter,
+                DiagnosticCategory::MalformedAppend,
+                DiagnosticCategory::UnterminatedString,
+            ]
+        );
+        assert_eq!(diags[0].location(), SourceLoc::new(1, 21));
+    }
+
+    #[test]
+    fn references() {
+        let text = "a = &Machines, b = &Quotes Machines 2.fields.id }\nc = &1";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        assert_eq!(tokens[2].token_val, TokenValue::Reference(vec!["Machines".to_string()]));
+        assert_eq!(
+            tokens[6].token_val,
+            TokenValue::Reference(vec!["Quotes Machines 2".to_string(), "fields".to_string(), "id".to_string()])
+        );
+        assert_eq!(&text[tokens[6].span.start..tokens[6].span.end], "&Quotes Machines 2.fields.id");
+        assert_eq!(tokens[7].token_val, TokenValue::CloseBrace);
+        assert_eq!(diags[0].category(), DiagnosticCategory::MalformedReference);
+    }
+
+    #[test]
+    fn append() {
+        let text = "Quotes += {}";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[1].token_val, TokenValue::Append);
+        assert_eq!(tokens[2].token_val, TokenValue::OpenBrace);
+    }
 }
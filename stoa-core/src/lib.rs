@@ -1,8 +1,26 @@
+pub mod check;
 pub mod diagnostic;
-mod error;
-mod keyvalue;
+pub mod diff;
+pub mod edit;
+pub mod error;
+pub mod graph;
+pub mod ids;
+pub mod include;
+pub mod keyvalue;
 pub mod lexer;
-mod r#macro;
+pub mod lint;
+pub mod merge;
+pub mod r#macro;
 pub mod parser;
+pub mod patch;
+pub mod printer;
 pub mod query;
-mod token;
+pub mod schema;
+pub mod sql;
+#[cfg(test)]
+mod testing;
+pub mod token;
+pub mod typed;
+pub mod validate;
+
+pub use error::{Error, Result};
@@ -0,0 +1,264 @@
+//! Lint rules: problems that don't stop a document from being read, but that
+//! are probably mistakes. Each rule is a [`DiagnosticCategory`] and can be
+//! turned off or given a different severity with a [`LintConfig`].
+
+use std::collections::HashMap;
+
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCategory, Severity},
+    error::{Error, Result},
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry},
+};
+
+/// Name of the block that configures lints in a config document.
+pub const CONFIG_BLOCK: &str = "lints";
+
+/// Which lint rules run, and how severe their diagnostics are.
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    /// `None` turns a rule off.
+    levels: HashMap<DiagnosticCategory, Option<Severity>>,
+}
+
+impl Default for LintConfig {
+    /// Every rule at its default severity, except `empty_blocks`, which is
+    /// off.
+    fn default() -> Self {
+        let mut levels = DiagnosticCategory::LINTS
+            .into_iter()
+            .map(|lint| (lint, Some(lint.severity())))
+            .collect::<HashMap<_, _>>();
+        levels.insert(DiagnosticCategory::EmptyBlock, None);
+        Self { levels }
+    }
+}
+
+impl LintConfig {
+    /// Sets the severity of `rule`, or turns it off with `None`.
+    pub fn set(&mut self, rule: DiagnosticCategory, level: Option<Severity>) {
+        self.levels.insert(rule, level);
+    }
+
+    pub fn level(&self, rule: DiagnosticCategory) -> Option<Severity> {
+        self.levels.get(&rule).copied().flatten()
+    }
+
+    /// Reads the `lints` block of a config document, which maps rule names or
+    /// codes to `"allow"`, `"note"`, `"warning"` or `"error"`:
+    ///
+    /// ```text
+    /// lints = {
+    ///   duplicate_keys = "error",
+    ///   unused_macros = "allow"
+    /// }
+    /// ```
+    pub fn from_block(config: &KeyValueBlock) -> Result<Self> {
+        let mut lints = Self::default();
+        let Some(entry) = config.entries.iter().find(|e| *e.key() == Key::Name(CONFIG_BLOCK.to_string())) else {
+            return Ok(lints);
+        };
+        let BlockValue::Block(block) = entry.value() else {
+            return Err(Error::InvalidConfig(format!("`{CONFIG_BLOCK}` must be a block"), entry.location()));
+        };
+
+        for entry in &block.entries {
+            let name = entry.key().to_string();
+            let rule = DiagnosticCategory::from_lint_name(&name)
+                .ok_or_else(|| Error::InvalidConfig(format!("unknown lint `{name}`"), entry.location()))?;
+            let level = match entry.value() {
+                BlockValue::Literal(level) => parse_level(level),
+                _ => None,
+            };
+            let level = level.ok_or_else(|| {
+                Error::InvalidConfig(
+                    format!("the level of `{name}` must be \"allow\", \"note\", \"warning\" or \"error\""),
+                    entry.location(),
+                )
+            })?;
+            lints.set(rule, level);
+        }
+        Ok(lints)
+    }
+}
+
+/// Parses `allow`, `note`, `warning` or `error`. `allow` turns a rule off.
+pub fn parse_level(level: &str) -> Option<Option<Severity>> {
+    match level {
+        "allow" => Some(None),
+        "note" => Some(Some(Severity::Note)),
+        "warning" => Some(Some(Severity::Warning)),
+        "error" => Some(Some(Severity::Error)),
+        _ => None,
+    }
+}
+
+/// Runs every enabled rule over a parsed document, before macro expansion.
+/// Duplicate keys are found once the document is resolved, by
+/// [`KeyValueBlock::resolve_duplicates`], and are not reported here.
+pub fn lint(document: &KeyValueBlock, config: &LintConfig) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    macros(document, &mut diagnostics);
+    blocks(document, &mut diagnostics);
+    casing(document, &mut diagnostics);
+
+    diagnostics
+        .into_iter()
+        .filter_map(|d| config.level(d.category()).map(|severity| d.with_severity(severity)))
+        .collect()
+}
+
+fn macros(document: &KeyValueBlock, diagnostics: &mut Vec<Diagnostic>) {
+    let mut definitions: HashMap<(&str, usize), &KeyValueEntry> = HashMap::new();
+    let mut order = vec![];
+    for entry in &document.entries {
+        let Key::MacroSignature { name, args } = entry.key() else {
+            continue;
+        };
+        let signature = (name.as_str(), args.len());
+        if let Some(previous) = definitions.insert(signature, entry) {
+            diagnostics.push(
+                Diagnostic::new(DiagnosticCategory::ShadowedMacro, entry.location(), String::new())
+                    .with_span(entry.span())
+                    .with_message(format!("macro `{name}`{} is defined again", arity(args.len())))
+                    .with_related(previous.location(), previous.span(), "the earlier definition is never used"),
+            );
+        } else {
+            order.push(signature);
+        }
+    }
+
+    for entry in &document.entries {
+        if let Key::MacroCall { name, args } = entry.key() {
+            definitions.remove(&(name.as_str(), args.len()));
+        }
+    }
+    for signature in order {
+        if let Some(entry) = definitions.get(&signature) {
+            diagnostics.push(
+                Diagnostic::new(DiagnosticCategory::UnusedMacro, entry.location(), String::new())
+                    .with_span(entry.span())
+                    .with_message(format!("macro `{}`{} is never called", signature.0, arity(signature.1))),
+            );
+        }
+    }
+}
+
+fn arity(n: usize) -> String {
+    match n {
+        0 => String::new(),
+        1 => " with 1 parameter".to_string(),
+        n => format!(" with {n} parameters"),
+    }
+}
+
+/// Empty blocks, recursively.
+fn blocks(block: &KeyValueBlock, diagnostics: &mut Vec<Diagnostic>) {
+    for entry in &block.entries {
+        if let BlockValue::Block(inner) = entry.value() {
+            if inner.entries.is_empty() {
+                diagnostics.push(
+                    Diagnostic::new(DiagnosticCategory::EmptyBlock, entry.location(), String::new())
+                        .with_span(entry.span())
+                        .with_message(format!("`{}` is an empty block", entry.key())),
+                );
+            }
+            blocks(inner, diagnostics);
+        }
+    }
+}
+
+/// Keys that are spelt the same apart from case and separators, such as
+/// `tableName` and `table_name`. Each spelling after the first is reported
+/// once.
+fn casing(document: &KeyValueBlock, diagnostics: &mut Vec<Diagnostic>) {
+    fn visit<'a>(
+        block: &'a KeyValueBlock,
+        spellings: &mut HashMap<String, Vec<(&'a str, &'a KeyValueEntry)>>,
+    ) {
+        for entry in &block.entries {
+            if let Key::Name(name) = entry.key() {
+                let normalised = name.replace(['_', ' ', '-'], "").to_lowercase();
+                let known = spellings.entry(normalised).or_default();
+                if !known.iter().any(|(spelling, _)| *spelling == name) {
+                    known.push((name, entry));
+                }
+            }
+            if let BlockValue::Block(inner) = entry.value() {
+                visit(inner, spellings);
+            }
+        }
+    }
+
+    let mut spellings = HashMap::new();
+    visit(document, &mut spellings);
+    let mut found = spellings.into_values().filter(|s| s.len() > 1).collect::<Vec<_>>();
+    found.sort_by_key(|s| s[0].1.location());
+
+    for spellings in found {
+        let (first, first_entry) = spellings[0];
+        for (spelling, entry) in &spellings[1..] {
+            diagnostics.push(
+                Diagnostic::new(DiagnosticCategory::InconsistentKeyCasing, entry.location(), String::new())
+                    .with_span(entry.span())
+                    .with_message(format!("`{spelling}` is spelt `{first}` elsewhere"))
+                    .with_related(first_entry.location(), first_entry.span(), format!("`{first}` is used here")),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        diagnostic::{DiagnosticCategory, Severity},
+        testing::document,
+        token::SourceLoc,
+    };
+
+    use super::{lint, LintConfig};
+
+    const SOURCE: &str = "\
+macro table($id) = { id = $id }
+macro table($id) = { id = $id, type = \"Table\" }
+macro unused = {}
+
+@table(1) = {}
+
+Quotes = { id = 1, fields = {}, id = 2 }
+Machines = { tableName = 1, table_name = 2 }
+";
+
+    #[test]
+    fn default_rules() {
+        let diagnostics = lint(&document(SOURCE), &LintConfig::default());
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.category(), d.location().line()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (DiagnosticCategory::ShadowedMacro, 2),
+                (DiagnosticCategory::UnusedMacro, 3),
+                (DiagnosticCategory::InconsistentKeyCasing, 8),
+            ]
+        );
+        assert_eq!(diagnostics[0].related()[0].location, SourceLoc::new(1, 1));
+        assert!(diagnostics.iter().all(|d| d.severity() == Severity::Warning));
+    }
+
+    #[test]
+    fn configured_rules() {
+        let config = document("lints = { key_casing = \"error\", L0002 = \"allow\", empty_blocks = \"note\" }");
+        let config = LintConfig::from_block(&config).unwrap();
+        let diagnostics = lint(&document(SOURCE), &config);
+
+        let casing = diagnostics.iter().find(|d| d.category() == DiagnosticCategory::InconsistentKeyCasing).unwrap();
+        assert_eq!(casing.severity(), Severity::Error);
+        assert!(!diagnostics.iter().any(|d| d.category() == DiagnosticCategory::UnusedMacro));
+        assert_eq!(diagnostics.iter().filter(|d| d.category() == DiagnosticCategory::EmptyBlock).count(), 3);
+
+        assert!(LintConfig::from_block(&document("lints = { nope = \"error\" }")).is_err());
+        assert!(LintConfig::from_block(&document("lints = { key_casing = \"loud\" }")).is_err());
+    }
+}
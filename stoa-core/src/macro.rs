@@ -1,7 +1,212 @@
+use std::collections::HashMap;
 
-use crate::keyvalue::KeyValueBlock;
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry};
 
+/// Name of the parameter bound to the value a macro is called with, as in
+/// `@table(1, Quotes) = { ... }`.
+const BODY: &str = "body";
+
+type Definitions = HashMap<(String, usize), (Vec<String>, BlockValue)>;
+
+/// Replaces every `@name(args) = value` call with the entries produced by the
+/// matching `macro name($params) = { ... }` definition, and removes the
+/// definitions. Macros are overloaded on their number of parameters.
+///
+/// Inside a definition, `$param` is replaced by the argument passed for it,
+/// whether it appears as a key or a value, and a bare `$body` entry is
+/// replaced by the entries of the block the macro was called with.
 pub fn expand_macros(intermediate_store: &mut KeyValueBlock) -> Result<()> {
+    expand(intermediate_store, false)
+}
+
+/// Like [`expand_macros`], but prefixes the entries each call produces with a
+/// comment naming the call.
+pub fn expand_macros_annotated(intermediate_store: &mut KeyValueBlock) -> Result<()> {
+    expand(intermediate_store, true)
+}
+
+fn expand(store: &mut KeyValueBlock, annotate: bool) -> Result<()> {
+    let mut definitions = Definitions::new();
+    let mut entries = vec![];
+
+    for entry in std::mem::take(&mut store.entries) {
+        if let Key::MacroSignature { name, args } = entry.key() {
+            let (name, args) = (name.clone(), args.clone());
+            definitions.insert((name, args.len()), (args, entry.value().clone()));
+        } else {
+            entries.push(entry);
+        }
+    }
+
+    for entry in entries {
+        let Key::MacroCall { name, args } = entry.key() else {
+            store.entries.push(entry);
+            continue;
+        };
+        let (params, body) = definitions
+            .get(&(name.clone(), args.len()))
+            .ok_or_else(|| Error::UndefinedMacro(name.clone(), args.len(), entry.location()))?;
+        let BlockValue::Block(body) = body else {
+            return Err(Error::MacroBodyNotBlock(format!("@{name}"), entry.location()));
+        };
+
+        let mut bindings = params
+            .iter()
+            .cloned()
+            .zip(args.iter().map(|a| argument_value(a)))
+            .collect::<HashMap<_, _>>();
+        bindings.insert(BODY.to_string(), entry.value().clone());
+
+        let mut expanded = substitute_block(body, &bindings)?.entries;
+        if let Some(first) = expanded.first_mut() {
+            let mut comments = entry.comments().to_vec();
+            if annotate {
+                comments.push(format!(" expanded from {}", entry.key()));
+            }
+            comments.extend(first.comments().iter().cloned());
+            *first = first.clone().with_comments(comments);
+        }
+        if let (Some(last), Some(comment)) = (expanded.last_mut(), entry.trailing_comment()) {
+            *last = last.clone().with_trailing_comment(Some(comment.to_string()));
+        }
+        for produced in expanded {
+            // Point diagnostics about the produced entries at the call.
+            store.entries.push(produced.relocated(entry.location(), entry.span()));
+        }
+    }
     Ok(())
 }
+
+/// Arguments are written as bare words, so numbers are recognised by shape.
+fn argument_value(arg: &str) -> BlockValue {
+    if let Ok(n) = arg.parse::<i64>() {
+        BlockValue::Integer(n)
+    } else if let Ok(n) = arg.parse::<f64>() {
+        BlockValue::Float(n)
+    } else {
+        BlockValue::Literal(arg.to_string())
+    }
+}
+
+fn bound<'a>(bindings: &'a HashMap<String, BlockValue>, param: &str, entry: &KeyValueEntry) -> Result<&'a BlockValue> {
+    bindings.get(param).ok_or_else(|| Error::UnboundMacroParameter(param.to_string(), entry.location()))
+}
+
+fn substitute_block(block: &KeyValueBlock, bindings: &HashMap<String, BlockValue>) -> Result<KeyValueBlock> {
+    let mut out = KeyValueBlock::new();
+    out.comments = block.comments.clone();
+
+    for entry in &block.entries {
+        match (entry.key(), entry.value()) {
+            (Key::MacroValue(param), BlockValue::Empty) => match bound(bindings, param, entry)? {
+                BlockValue::Block(inner) => out.entries.extend(inner.entries.iter().cloned()),
+                _ => return Err(Error::MacroBodyNotBlock(format!("${param}"), entry.location())),
+            },
+            (Key::MacroValue(param), value) => {
+                let name = match bound(bindings, param, entry)? {
+                    BlockValue::Literal(s) => s.clone(),
+                    BlockValue::Integer(n) => n.to_string(),
+                    BlockValue::Float(n) => n.to_string(),
+                    _ => return Err(Error::UnboundMacroParameter(param.clone(), entry.location())),
+                };
+                out.entries.push(replaced(entry, Key::Name(name), substitute_value(value, bindings, entry)?));
+            }
+            (key, value) => {
+                out.entries.push(replaced(entry, key.clone(), substitute_value(value, bindings, entry)?));
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn substitute_value(value: &BlockValue, bindings: &HashMap<String, BlockValue>, entry: &KeyValueEntry) -> Result<BlockValue> {
+    Ok(match value {
+        BlockValue::MacroValue(param) => bound(bindings, param, entry)?.clone(),
+        BlockValue::Block(block) => BlockValue::Block(substitute_block(block, bindings)?),
+        value => value.clone(),
+    })
+}
+
+/// A copy of `entry` with a new key and value, keeping its location.
+fn replaced(entry: &KeyValueEntry, key: Key, value: BlockValue) -> KeyValueEntry {
+    KeyValueEntry::new(key, entry.location(), value)
+        .with_span(entry.span(), entry.value_span())
+        .with_comments(entry.comments().to_vec())
+        .with_trailing_comment(entry.trailing_comment().map(str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{keyvalue::{BlockValue, Key}, lexer::lex, parser::parse, printer::to_source, query};
+
+    use super::{expand_macros, expand_macros_annotated};
+
+    const SOURCE: &str = "\
+macro table($id, $name) = {
+  $name = {
+    id = $id,
+    type = \"Table\",
+    $body
+  }
+}
+
+macro table($name) = {
+  $name = { type = \"Table\" }
+}
+
+@table(1, Quotes) = {
+  fields = {}
+}
+
+@table(Machines) = {}
+";
+
+    #[test]
+    fn expands_calls() {
+        let mut diags = vec![];
+        let tokens = lex(SOURCE, &mut diags).unwrap();
+        let mut store = parse(&tokens, &mut diags).unwrap();
+        expand_macros(&mut store).unwrap();
+
+        assert_eq!(
+            to_source(&store),
+            "Quotes = {\n  id = 1,\n  type = \"Table\",\n  fields = {}\n}\n\nMachines = {\n  type = \"Table\"\n}\n"
+        );
+        let id = query::get(&store, &["Quotes".to_string(), "id".to_string()]);
+        assert_eq!(id, Some(&BlockValue::Integer(1)));
+    }
+
+    #[test]
+    fn annotates_calls() {
+        let mut diags = vec![];
+        let tokens = lex(SOURCE, &mut diags).unwrap();
+        let mut store = parse(&tokens, &mut diags).unwrap();
+        expand_macros_annotated(&mut store).unwrap();
+
+        assert_eq!(store.entries[0].comments(), [" expanded from @table(1, Quotes)"]);
+        assert_eq!(*store.entries[1].key(), Key::Name("Machines".to_string()));
+
+        let tokens = lex("macro t($n) = { $n = {} }\n@t(A) = {} # kept\n", &mut diags).unwrap();
+        let mut store = parse(&tokens, &mut diags).unwrap();
+        expand_macros_annotated(&mut store).unwrap();
+        assert_eq!(store.entries[0].trailing_comment(), Some(" kept"));
+    }
+
+    #[test]
+    fn keeps_bare_names() {
+        let mut diags = vec![];
+        let tokens = lex("macro field($n) = { $n = { required } }\n@field(title) = {}\n", &mut diags).unwrap();
+        let mut store = parse(&tokens, &mut diags).unwrap();
+        expand_macros(&mut store).unwrap();
+        assert_eq!(to_source(&store), "title = {\n  required\n}\n");
+    }
+
+    #[test]
+    fn undefined_macro() {
+        let mut diags = vec![];
+        let tokens = lex("@table(1, 2, 3) = {}", &mut diags).unwrap();
+        let mut store = parse(&tokens, &mut diags).unwrap();
+        assert!(expand_macros(&mut store).is_err());
+    }
+}
@@ -0,0 +1,420 @@
+//! Three-way merges of documents as they are written. Blocks are merged key
+//! by key, so edits to different keys never conflict, whatever their layout.
+//! A key both sides changed differently is a conflict, which keeps our side
+//! in the merged document until it is resolved.
+//!
+//! A top-level entry one side renamed, keeping its `type` and `id`, is
+//! renamed on the other side too before merging, so edits made to it under
+//! the old name still apply. An entry the two sides renamed differently is a
+//! conflict.
+
+use std::fmt::Write;
+
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCategory},
+    diff::{diff, same, Change},
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry, Operator},
+    patch::Patch,
+    printer::entry_to_source_with,
+};
+
+const INDENT: usize = 2;
+
+/// A key that was changed differently on each side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub path: Vec<String>,
+    pub base: Option<BlockValue>,
+    pub ours: Option<KeyValueEntry>,
+    pub theirs: Option<KeyValueEntry>,
+}
+
+impl Conflict {
+    /// Reports the conflict at our side, related to theirs.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let path = self.path.join(".");
+        let message = match (&self.base, &self.ours, &self.theirs) {
+            (Some(_), Some(ours), Some(theirs)) if ours.key() != theirs.key() => format!(
+                "both sides renamed the same entry, to `{}` in ours and `{}` in theirs",
+                ours.key(),
+                theirs.key()
+            ),
+            (None, ..) => format!("`{path}` was added on both sides with different values"),
+            (Some(_), None, _) => format!("`{path}` was removed in ours but changed in theirs"),
+            (Some(_), _, None) => format!("`{path}` was removed in theirs but changed in ours"),
+            _ => format!("`{path}` was changed on both sides"),
+        };
+        let (first, second) = match (&self.ours, &self.theirs) {
+            (Some(ours), theirs) => (ours, theirs.as_ref()),
+            (None, Some(theirs)) => (theirs, None),
+            (None, None) => unreachable!("a conflict has at least one side"),
+        };
+        let diagnostic = Diagnostic::new(DiagnosticCategory::MergeConflict, first.location(), String::new())
+            .with_span(first.span())
+            .with_message(message);
+        match second {
+            Some(theirs) => diagnostic.with_related(theirs.location(), theirs.span(), "their side"),
+            None => diagnostic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged {
+    /// The merged document, with our side of each conflict.
+    pub document: KeyValueBlock,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Merges the changes `ours` and `theirs` each made to `base`.
+pub fn merge(base: &KeyValueBlock, ours: &KeyValueBlock, theirs: &KeyValueBlock) -> Merged {
+    let (mut base, mut ours, mut theirs) = (base.clone(), ours.clone(), theirs.clone());
+    let mut conflicts = vec![];
+    let ours_renames = renames(&base, &ours);
+    for (from, to) in &ours_renames {
+        if let Some((_, other)) = renames(&base, &theirs).into_iter().find(|(f, t)| f == from && t != to) {
+            conflicts.extend(divergent_rename(&base, &ours, &mut theirs, from, to, &other));
+        }
+    }
+    for (from, to) in ours_renames {
+        follow_rename(&mut base, &mut theirs, from, to);
+    }
+    for (from, to) in renames(&base, &theirs) {
+        follow_rename(&mut base, &mut ours, from, to);
+    }
+
+    let document = merge_blocks(Some(&base), &ours, &theirs, &[], &mut conflicts);
+    Merged { document, conflicts }
+}
+
+/// The top-level entries `side` renamed from `base`.
+fn renames(base: &KeyValueBlock, side: &KeyValueBlock) -> Vec<(String, String)> {
+    diff(base, side)
+        .into_iter()
+        .filter_map(|change| match change {
+            Change::Renamed { from, to } => Some((from, to)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Renames `from` in the base and the other side, if the other side still
+/// has it under that name.
+fn follow_rename(base: &mut KeyValueBlock, other: &mut KeyValueBlock, from: String, to: String) {
+    let rename = Patch::from_changes(vec![Change::Renamed { from, to }]);
+    let mut renamed = other.clone();
+    if rename.apply(&mut renamed).is_empty() && rename.apply(base).is_empty() {
+        *other = renamed;
+    }
+}
+
+/// The conflict between renaming `from` to `to` in ours and to `other` in
+/// theirs. Their entry is taken out of the merge, which keeps ours.
+fn divergent_rename(
+    base: &KeyValueBlock,
+    ours: &KeyValueBlock,
+    theirs: &mut KeyValueBlock,
+    from: &str,
+    to: &str,
+    other: &str,
+) -> Option<Conflict> {
+    let find = |block: &KeyValueBlock, name: &str| block.entries.iter().rposition(|e| e.key().to_string() == name);
+    let (ours_index, theirs_index) = (find(ours, to)?, find(theirs, other)?);
+    Some(Conflict {
+        path: vec![to.to_string()],
+        base: find(base, from).map(|index| base.entries[index].value().clone()),
+        ours: Some(ours.entries[ours_index].clone()),
+        theirs: Some(theirs.entries.remove(theirs_index)),
+    })
+}
+
+fn merge_blocks(
+    base: Option<&KeyValueBlock>,
+    ours: &KeyValueBlock,
+    theirs: &KeyValueBlock,
+    path: &[String],
+    conflicts: &mut Vec<Conflict>,
+) -> KeyValueBlock {
+    let mut keys: Vec<&Key> = vec![];
+    for entry in ours.entries.iter().chain(&theirs.entries) {
+        if !keys.contains(&entry.key()) {
+            keys.push(entry.key());
+        }
+    }
+
+    let mut merged = KeyValueBlock { comments: ours.comments.clone(), ..KeyValueBlock::new() };
+    for key in keys {
+        let find = |block: &KeyValueBlock| block.entries.iter().rev().find(|e| e.key() == key).cloned();
+        let path = [path, &[key.to_string()]].concat();
+        let base = base.and_then(find);
+        if let Some(entry) = merge_entries(base.as_ref(), find(ours), find(theirs), path, conflicts) {
+            merged.add(entry);
+        }
+    }
+    merged
+}
+
+fn merge_entries(
+    base: Option<&KeyValueEntry>,
+    ours: Option<KeyValueEntry>,
+    theirs: Option<KeyValueEntry>,
+    path: Vec<String>,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<KeyValueEntry> {
+    let unchanged = |a: Option<&KeyValueEntry>, b: Option<&KeyValueEntry>| match (value(a), value(b)) {
+        (Some(a), Some(b)) => same(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    };
+
+    if unchanged(ours.as_ref(), theirs.as_ref()) || unchanged(base, theirs.as_ref()) {
+        return ours;
+    }
+    if unchanged(base, ours.as_ref()) {
+        return theirs;
+    }
+    match (value(base), value(ours.as_ref()), value(theirs.as_ref())) {
+        (None | Some(BlockValue::Block(_)), Some(BlockValue::Block(o)), Some(BlockValue::Block(t))) => {
+            let base = match value(base) {
+                Some(BlockValue::Block(b)) => Some(b),
+                _ => None,
+            };
+            let block = merge_blocks(base, o, t, &path, conflicts);
+            let mut entry = ours?;
+            *entry.value_mut() = BlockValue::Block(block);
+            Some(entry)
+        }
+        _ => {
+            conflicts.push(Conflict { path, base: value(base).cloned(), ours: ours.clone(), theirs });
+            ours
+        }
+    }
+}
+
+fn value(entry: Option<&KeyValueEntry>) -> Option<&BlockValue> {
+    entry.map(KeyValueEntry::value)
+}
+
+impl Merged {
+    /// The merged document in the canonical layout, with each conflict
+    /// written out between `<<<<<<< ours`, `=======` and `>>>>>>> theirs`
+    /// lines, as git does.
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        let items = self.items(&self.document, &[]);
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            self.write_item(&mut out, item, &[], 0, "");
+            out.push('\n');
+        }
+        if !self.document.comments.is_empty() && !items.is_empty() {
+            out.push('\n');
+        }
+        for comment in &self.document.comments {
+            let _ = writeln!(out, "#{comment}");
+        }
+        out
+    }
+
+    /// The entries of the block at `path` and the conflicts in it. A conflict
+    /// over a key we removed goes after the other entries.
+    fn items<'a>(&'a self, block: &'a KeyValueBlock, path: &[String]) -> Vec<Item<'a>> {
+        let at = |conflict: &Conflict| conflict.path.split_last().is_some_and(|(_, parent)| parent == path);
+        let mut items = block
+            .entries
+            .iter()
+            .map(|entry| {
+                let key = [path, &[entry.key().to_string()]].concat();
+                match self.conflicts.iter().find(|c| c.path == key) {
+                    Some(conflict) => Item::Conflict(conflict),
+                    None => Item::Entry(entry),
+                }
+            })
+            .collect::<Vec<_>>();
+        items.extend(self.conflicts.iter().filter(|c| c.ours.is_none() && at(c)).map(Item::Conflict));
+        items
+    }
+
+    fn write_item(&self, out: &mut String, item: &Item, path: &[String], indent: usize, separator: &str) {
+        let entry = match item {
+            Item::Conflict(conflict) => {
+                let side = |entry: &Option<KeyValueEntry>| match entry {
+                    Some(entry) => format!("{:indent$}{}\n", "", entry_to_source_with(entry, indent, separator)),
+                    None => String::new(),
+                };
+                let (ours, theirs) = (side(&conflict.ours), side(&conflict.theirs));
+                let _ = write!(out, "<<<<<<< ours\n{ours}=======\n{theirs}>>>>>>> theirs");
+                return;
+            }
+            Item::Entry(entry) => entry,
+        };
+        let path = [path, &[entry.key().to_string()]].concat();
+        let block = match entry.value() {
+            BlockValue::Block(block) if self.conflicts.iter().any(|c| c.path.starts_with(&path)) => block,
+            _ => {
+                let _ = write!(out, "{:indent$}{}", "", entry_to_source_with(entry, indent, separator));
+                return;
+            }
+        };
+
+        for comment in entry.comments() {
+            let _ = writeln!(out, "{:indent$}#{comment}", "");
+        }
+        let operator = match entry.operator() {
+            Operator::Assign => "=",
+            Operator::Append => "+=",
+        };
+        let _ = writeln!(out, "{:indent$}{} {operator} {{", "", entry.key());
+        let items = self.items(block, &path);
+        for (i, item) in items.iter().enumerate() {
+            let separator = if i + 1 < items.len() { "," } else { "" };
+            self.write_item(out, item, &path, indent + INDENT, separator);
+            out.push('\n');
+        }
+        for comment in &block.comments {
+            let _ = writeln!(out, "{:width$}#{comment}", "", width = indent + INDENT);
+        }
+        let _ = write!(out, "{:indent$}}}{separator}", "");
+        if let Some(comment) = entry.trailing_comment() {
+            let _ = write!(out, " #{comment}");
+        }
+    }
+}
+
+enum Item<'a> {
+    Entry(&'a KeyValueEntry),
+    Conflict(&'a Conflict),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        printer::to_source,
+        testing::document_in,
+        token::SourceLoc,
+    };
+
+    use super::merge;
+
+    const BASE: &str = "\
+Quotes = { id = 1, type = \"Table\", fields = { title = \"Text\", total = \"Number\" } }
+Machines = { id = 2, type = \"Table\" }
+";
+
+    #[test]
+    fn merges_separate_edits() {
+        let ours = "\
+Quotes = { id = 1, type = \"Table\", fields = { title = \"Text\", total = \"Integer\" } }
+Devices = { id = 2, type = \"Table\" }
+";
+        let theirs = "\
+# Reformatted, with a new field
+Quotes = {
+  type = \"Table\",
+  id = 1,
+  fields = { total = \"Number\", title = \"Text\", made = \"Date\" }
+}
+Machines = { id = 2, type = \"Table\", kind = \"Press\" }
+";
+        let merged = merge(&document_in(BASE, 0), &document_in(ours, 1), &document_in(theirs, 2));
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(
+            to_source(&merged.document),
+            "\
+Quotes = {
+  id = 1,
+  type = \"Table\",
+  fields = {
+    title = \"Text\",
+    total = \"Integer\",
+    made = \"Date\"
+  }
+}
+
+Devices = {
+  id = 2,
+  type = \"Table\",
+  kind = \"Press\"
+}
+"
+        );
+    }
+
+    #[test]
+    fn marks_conflicts() {
+        let ours = "\
+Quotes = { id = 1, type = \"Table\", fields = { title = \"Text\", total = \"Integer\" } }
+";
+        let theirs = "\
+Quotes = { id = 1, type = \"Table\", fields = { title = \"Text\", total = \"Money\" } }
+Machines = { id = 2, type = \"Table\", kind = \"Press\" }
+";
+        let merged = merge(&document_in(BASE, 0), &document_in(ours, 1), &document_in(theirs, 2));
+        assert_eq!(
+            merged.to_source(),
+            "\
+Quotes = {
+  id = 1,
+  type = \"Table\",
+  fields = {
+    title = \"Text\",
+<<<<<<< ours
+    total = \"Integer\"
+=======
+    total = \"Money\"
+>>>>>>> theirs
+  }
+}
+
+<<<<<<< ours
+=======
+Machines = {
+  id = 2,
+  type = \"Table\",
+  kind = \"Press\"
+}
+>>>>>>> theirs
+"
+        );
+
+        let diagnostics = merged.conflicts.iter().map(|c| c.to_diagnostic()).collect::<Vec<_>>();
+        assert_eq!(diagnostics[0].message(), "`Quotes.fields.total` was changed on both sides");
+        assert_eq!(diagnostics[0].location(), SourceLoc::new(1, 63).with_file(1));
+        assert_eq!(diagnostics[0].related()[0].location, SourceLoc::new(1, 63).with_file(2));
+        assert_eq!(diagnostics[1].message(), "`Machines` was removed in ours but changed in theirs");
+
+        // Without conflicts, the layout is the printer's.
+        let merged = merge(&document_in(BASE, 0), &document_in(BASE, 1), &document_in(BASE, 2));
+        assert_eq!(merged.to_source(), to_source(&document_in(BASE, 0)));
+    }
+
+    #[test]
+    fn marks_divergent_renames() {
+        let quotes = "Quotes = { id = 1, type = \"Table\", fields = { title = \"Text\", total = \"Number\" } }\n";
+        let ours = format!("{quotes}Devices = {{ id = 2, type = \"Table\" }}\n");
+        let theirs = format!("{quotes}Presses = {{ id = 2, type = \"Table\" }}\n");
+        let merged = merge(&document_in(BASE, 0), &document_in(&ours, 1), &document_in(&theirs, 2));
+        let keys = merged.document.entries.iter().map(|e| e.key().to_string()).collect::<Vec<_>>();
+        assert_eq!(keys, ["Quotes", "Devices"]);
+        assert_eq!(
+            merged.conflicts[0].to_diagnostic().message(),
+            "both sides renamed the same entry, to `Devices` in ours and `Presses` in theirs"
+        );
+        assert!(merged.to_source().ends_with(
+            "\
+<<<<<<< ours
+Devices = {
+  id = 2,
+  type = \"Table\"
+}
+=======
+Presses = {
+  id = 2,
+  type = \"Table\"
+}
+>>>>>>> theirs
+"
+        ));
+    }
+}
@@ -1,50 +1,135 @@
-use crate::diagnostic::Diagnostic;
+use std::collections::HashMap;
+
+use crate::diagnostic::{Diagnostic, DiagnosticCategory};
 use crate::error::{Error, Result};
-use crate::token::{SourceLoc, Token, TokenKind, TokenValue};
+use crate::token::{SourceLoc, Span, Token, TokenKind, TokenValue};
 use crate::keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry};
 
+/// Key under which comments after the last token are stored.
+const END_OF_INPUT: usize = usize::MAX;
+
+/// Tokens that can start a value.
+const VALUE: &[TokenKind] = &[
+    TokenKind::OpenBrace,
+    TokenKind::IntegerLiteral,
+    TokenKind::FloatLiteral,
+    TokenKind::String,
+    TokenKind::MacroParameter,
+    TokenKind::Reference,
+];
+/// Tokens that can follow an entry inside a block.
+const SEPARATOR: &[TokenKind] = &[TokenKind::Comma, TokenKind::CloseBrace];
+const TOP_LEVEL_KEY: &[TokenKind] = &[TokenKind::Identifier, TokenKind::Macro, TokenKind::MacroCall];
+const BLOCK_KEY: &[TokenKind] = &[TokenKind::Identifier, TokenKind::MacroParameter, TokenKind::CloseBrace];
+const OPERATOR: &[TokenKind] = &[TokenKind::Assignment, TokenKind::Append];
+
 struct Parser<'a> {
-    stream: std::iter::Peekable<std::slice::Iter<'a, Token>>,
+    stream: std::iter::Peekable<std::vec::IntoIter<&'a Token>>,
     current: Option<&'a Token>,
+    /// Comments, keyed by the byte offset of the token that follows them.
+    comments: HashMap<usize, Vec<String>>,
+    /// Comments that begin on the line a token ends, after its separating
+    /// comma if any: the byte offset just past that token, mapped to the key
+    /// of the comments. If the token ends an entry, the first of them trails
+    /// the entry.
+    trailing: HashMap<usize, usize>,
+    /// Span of the most recently parsed value.
+    last_value: Option<Span>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: &'a [Token]) -> Self {
+        let mut significant = vec![];
+        let mut comments = HashMap::new();
+        let mut trailing = HashMap::new();
+        let mut pending = vec![];
+        // The last token other than a comma, and the end of the token the
+        // pending comments began on the line of.
+        let mut previous: Option<&Token> = None;
+        let mut after = None;
+
+        for token in tokens {
+            match &token.token_val {
+                TokenValue::Comment(text) => {
+                    if pending.is_empty() {
+                        after = previous.filter(|p| p.source_loc.line() == token.source_loc.line()).map(|p| p.span.end);
+                    }
+                    pending.push(text.clone());
+                }
+                _ => {
+                    if !pending.is_empty() {
+                        comments.insert(token.span.start, std::mem::take(&mut pending));
+                        if let Some(end) = after.take() {
+                            trailing.insert(end, token.span.start);
+                        }
+                    }
+                    if token.token_val != TokenValue::Comma {
+                        previous = Some(token);
+                    }
+                    significant.push(token);
+                }
+            }
+        }
+        if !pending.is_empty() {
+            comments.insert(END_OF_INPUT, pending);
+            if let Some(end) = after {
+                trailing.insert(end, END_OF_INPUT);
+            }
+        }
+
         Self {
-            stream: tokens.iter().peekable(),
+            stream: significant.into_iter().peekable(),
             current: None,
+            comments,
+            trailing,
+            last_value: None,
         }
     }
 
-    pub fn next(&mut self) -> Option<&&Token> {
+    pub fn next(&mut self) -> Option<&'a Token> {
         self.current = self.stream.next();
-        self.current.as_ref()
+        self.current
     }
 
-    pub fn peek(&mut self) -> Option<&&Token> {
-        self.stream.peek()
+    pub fn peek(&mut self) -> Option<&'a Token> {
+        self.stream.peek().copied()
     }
 
     pub fn expect(&mut self, expected: TokenKind) -> Result<&Token> {
-        let next = self.stream.next().ok_or(Error::UnexpectedEOF)?;
+        let Some(next) = self.stream.next() else {
+            return Err(self.eof(&[expected]));
+        };
         self.current = Some(next);
 
-        let current = self.current.as_ref().unwrap();
-        if current.token_val.kind() == expected {
-            return Ok(current)
+        if next.token_val.kind() == expected {
+            Ok(next)
         } else {
-            return Err(Error::UnexpectedToken((*current).clone()))
+            Err(unexpected(next, &[expected]))
         }
     }
 
-    pub fn expect_object_id(&mut self) -> Result<i64> {
-        let tmp = self.expect(TokenKind::IntegerLiteral)?;
-        match tmp.token_val {
-            TokenValue::IntegerLiteral(n) => Ok(n),
-            _ => unreachable!()
+    /// Consumes the next token, failing if the input has run out.
+    fn next_or_eof(&mut self, expected: &[TokenKind]) -> Result<&'a Token> {
+        match self.peek() {
+            Some(_) => Ok(self.next().unwrap()),
+            None => Err(self.eof(expected)),
         }
     }
 
+    fn peek_or_eof(&mut self, expected: &[TokenKind]) -> Result<&'a Token> {
+        self.peek().ok_or_else(|| self.eof(expected))
+    }
+
+    /// An error for input that ended while one of `expected` was wanted,
+    /// located just past the last token.
+    fn eof(&self, expected: &[TokenKind]) -> Error {
+        let location = self.current.map_or(SourceLoc::new(1, 1), |t| {
+            let width = (t.span.end - t.span.start) as u32;
+            SourceLoc::new(t.source_loc.line(), t.source_loc.column() + width).with_file(t.source_loc.file())
+        });
+        Error::UnexpectedEOF(location, expected.to_vec())
+    }
+
     pub fn expect_identifier(&mut self) -> Result<String> {
         let tmp = self.expect(TokenKind::Identifier)?;
         match &tmp.token_val {
@@ -52,79 +137,193 @@ impl<'a> Parser<'a> {
             _ => unreachable!()
         }
     }
+
+    /// Where the last consumed token starts, for reporting errors.
+    fn location(&self) -> SourceLoc {
+        self.current.map_or(SourceLoc::new(1, 1), |t| t.source_loc)
+    }
+
+    /// Byte offset just past the last consumed token.
+    fn end(&self) -> usize {
+        self.current.map_or(0, |t| t.span.end)
+    }
+
+    fn take_comments(&mut self, token: &Token) -> Vec<String> {
+        self.comments.remove(&token.span.start).unwrap_or_default()
+    }
+
+    /// Attaches the source span and comments to an entry that began at
+    /// `start` and has just been parsed.
+    fn finish_entry(&mut self, entry: KeyValueEntry, start: &Token) -> KeyValueEntry {
+        let value_span = self.last_value
+            .take()
+            .filter(|_| *entry.value() != BlockValue::Empty);
+        let span = Span::new(start.span.start, self.end());
+        let comments = self.take_comments(start);
+        let trailing = self.trailing.remove(&span.end).and_then(|next| {
+            let group = self.comments.get_mut(&next)?;
+            let comment = group.remove(0);
+            if group.is_empty() {
+                self.comments.remove(&next);
+            }
+            Some(comment)
+        });
+        entry.with_span(span, value_span).with_comments(comments).with_trailing_comment(trailing)
+    }
+}
+
+/// An error for `token`, which is not one of the `expected` kinds.
+fn unexpected(token: &Token, expected: &[TokenKind]) -> Error {
+    Error::UnexpectedToken(token.clone(), expected.to_vec())
 }
 
 fn parse_macro_key(identifier: String, start_loc: SourceLoc, parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>) -> Result<KeyValueEntry> {
-    let next = parser.next().ok_or(Error::UnexpectedEOF)?;
+    // The separator is left for the enclosing block to consume.
+    let next = parser.peek_or_eof(&[TokenKind::Assignment, TokenKind::Comma, TokenKind::CloseBrace])?;
 
     match &next.token_val {
-        TokenValue::Comma => Ok(KeyValueEntry::new(
+        TokenValue::Comma | TokenValue::CloseBrace => Ok(KeyValueEntry::new(
             Key::MacroValue(identifier),
             start_loc,
             BlockValue::Empty)),
-        TokenValue::Assignment => Ok(KeyValueEntry::new(
-            Key::MacroValue(identifier),
-            start_loc,
-            parse_value(parser, diagnostics)?)),
-        _ => return Err(Error::UnexpectedToken((*next).clone()))
+        TokenValue::Assignment => {
+            parser.next();
+            Ok(KeyValueEntry::new(
+                Key::MacroValue(identifier),
+                start_loc,
+                parse_value(parser, diagnostics)?))
+        }
+        _ => Err(unexpected(next, &[TokenKind::Assignment, TokenKind::Comma, TokenKind::CloseBrace]))
     }
 }
 
 fn parse_block(parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>) -> Result<KeyValueBlock> {
-    let mut block = KeyValueBlock { entries: vec![] };
+    let mut block = KeyValueBlock::new();
+    let open = parser.current;
 
     while let Some(token) = parser.next() {
-        match &token.token_val {
-            TokenValue::Identifier(s) => {
-                block.entries.push(parse_identifier_key(s.to_string(), token.source_loc, parser, diagnostics)?);
-                let peeked = parser.peek().ok_or(Error::UnexpectedEOF)?;
-                if ![TokenValue::Comma, TokenValue::CloseBrace]
-                    .contains(&peeked.token_val) {
-                    return Err(Error::UnexpectedToken((*peeked).clone()))
-                }
+        let entry = match &token.token_val {
+            TokenValue::Identifier(s) => parse_identifier_key(s.to_string(), token.source_loc, parser, diagnostics),
+            TokenValue::MacroParameter(s) => parse_macro_key(s.to_string(), token.source_loc, parser, diagnostics),
+            TokenValue::Comma => continue,
+            TokenValue::CloseBrace => {
+                block.comments = parser.take_comments(token);
+                return Ok(block)
             },
-            TokenValue::MacroParameter(s) => {
-                block.entries.push(parse_macro_key(s.to_string(), token.source_loc, parser, diagnostics)?);
-                let peeked = parser.peek().ok_or(Error::UnexpectedEOF)?;
-                if ![TokenValue::Comma, TokenValue::CloseBrace]
-                    .contains(&peeked.token_val) {
-                    return Err(Error::UnexpectedToken((*peeked).clone()))
+            _ => Err(unexpected(token, BLOCK_KEY))
+        };
+
+        match entry {
+            Ok(entry) => {
+                block.entries.push(parser.finish_entry(entry, token));
+                // A missing separator is reported, but the next entry is
+                // still parsed as if it were there.
+                match parser.peek() {
+                    Some(peeked) if ![TokenValue::Comma, TokenValue::CloseBrace].contains(&peeked.token_val) => {
+                        report(parser, diagnostics, unexpected(peeked, SEPARATOR));
+                    }
+                    _ => {}
                 }
-            },
-            TokenValue::Comma => continue,
-            TokenValue::CloseBrace => return Ok(block),
-            _ => return Err(Error::UnexpectedToken((*token).clone()))
+            }
+            Err(e) => {
+                report(parser, diagnostics, e);
+                if recover_entry(parser) {
+                    return Ok(block)
+                }
+            }
         }
     }
-    return Err(Error::UnexpectedEOF)
+    let (location, span) = open.map_or((parser.location(), Span::default()), |t| (t.source_loc, t.span));
+    diagnostics.push(
+        Diagnostic::new(DiagnosticCategory::UnclosedBlock, location, String::new())
+            .with_span(span)
+            .with_help("add a `}` to close the block"),
+    );
+    Ok(block)
 }
 
-fn parse_value(parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>) -> Result<BlockValue> {
-    let token = parser.next().ok_or(Error::UnexpectedEOF)?; 
-
-    match &token.token_val {
-        TokenValue::OpenBrace => Ok(BlockValue::Block(parse_block(parser, diagnostics)?)),
-        TokenValue::IntegerLiteral(n) => Ok(BlockValue::Literal(n.to_string())),
-        TokenValue::String(s) => Ok(BlockValue::Literal(s.to_string())),
-        TokenValue::MacroParameter(p) => Ok(BlockValue::MacroValue(p.to_string())),
-        _ => return Err(Error::UnexpectedToken((*token).clone()))
+/// Records `error` as a diagnostic at the token the parser stopped on.
+fn report(parser: &Parser, diagnostics: &mut Vec<Diagnostic>, error: Error) {
+    diagnostics.push(Diagnostic::from_error(&error, parser.location()));
+}
+
+/// Skips the rest of a malformed entry, up to and including the `,` that
+/// ends it or up to the `}` that closes the enclosing block. Returns true if
+/// that `}` was the token the entry failed on, so the block is already closed.
+fn recover_entry(parser: &mut Parser) -> bool {
+    let mut depth = 0;
+    match parser.current.map(|t| &t.token_val) {
+        Some(TokenValue::CloseBrace) => return true,
+        Some(TokenValue::OpenBrace) => depth += 1,
+        _ => {}
+    }
+
+    while let Some(token) = parser.peek() {
+        match token.token_val {
+            TokenValue::OpenBrace => depth += 1,
+            TokenValue::CloseBrace if depth == 0 => return false,
+            TokenValue::CloseBrace => depth -= 1,
+            TokenValue::Comma if depth == 0 => {
+                parser.next();
+                return false
+            },
+            _ => {}
+        }
+        parser.next();
     }
+    false
 }
 
-fn parse_identifier_value(identifier: String, start_loc: SourceLoc, parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>) -> Result<BlockValue> {
-    let next = parser.next().ok_or(Error::UnexpectedEOF)?;
+/// Skips to the next token that could start a top level entry: the first
+/// token on a line that is outside of any braces.
+fn recover_top_level(parser: &mut Parser) {
+    let mut depth: usize = 0;
+    if let Some(TokenValue::OpenBrace) = parser.current.map(|t| &t.token_val) {
+        depth += 1;
+    }
+    let mut line = parser.location().line();
 
-    match &next.token_val {
-        TokenValue::Identifier(s) => parse_identifier_value(identifier + " " +  &s, start_loc, parser, diagnostics),
-        TokenValue::Comma => return Ok(
-            BlockValue::Literal(identifier)
-        ),
-        _ => return Err(Error::UnexpectedToken((*next).clone()))
+    while let Some(token) = parser.peek() {
+        let starts_line = token.source_loc.line() > line;
+        match token.token_val {
+            TokenValue::OpenBrace => depth += 1,
+            TokenValue::CloseBrace => depth = depth.saturating_sub(1),
+            TokenValue::Identifier(_) | TokenValue::Macro | TokenValue::MacroCall(_)
+                if depth == 0 && starts_line => return,
+            _ => {}
+        }
+        line = token.source_loc.line();
+        parser.next();
     }
 }
 
+fn parse_value(parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>) -> Result<BlockValue> {
+    let token = parser.next_or_eof(VALUE)?;
+
+    let value = match &token.token_val {
+        TokenValue::OpenBrace => BlockValue::Block(parse_block(parser, diagnostics)?),
+        TokenValue::IntegerLiteral(n) => BlockValue::Integer(*n),
+        TokenValue::FloatLiteral(n) => BlockValue::Float(*n),
+        TokenValue::String(s) => BlockValue::Literal(s.to_string()),
+        TokenValue::MacroParameter(p) => BlockValue::MacroValue(p.to_string()),
+        TokenValue::Reference(path) => BlockValue::Reference(path.clone()),
+        _ => return Err(unexpected(token, VALUE))
+    };
+    parser.last_value = Some(Span::new(token.span.start, parser.end()));
+    Ok(value)
+}
+
 fn parse_identifier_key(identifier: String, start_loc: SourceLoc, parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>) -> Result<KeyValueEntry> {
-    let next = parser.next().ok_or(Error::UnexpectedEOF)?;
+    let next = parser.peek_or_eof(OPERATOR)?;
+
+    // The separator is left for the enclosing block to consume.
+    if [TokenValue::Comma, TokenValue::CloseBrace].contains(&next.token_val) {
+        return Ok(KeyValueEntry::new(
+            Key::Bare(identifier),
+            start_loc,
+            BlockValue::Empty))
+    }
+    parser.next();
 
     match &next.token_val {
         TokenValue::Identifier(s) => parse_identifier_key(identifier + " " + s, start_loc, parser, diagnostics),
@@ -135,24 +334,27 @@ fn parse_identifier_key(identifier: String, start_loc: SourceLoc, parser: &mut P
                 Key::MacroValue(p.to_string()),
                 start_loc,
                 match parser.peek().filter(|t| t.token_val.kind() == TokenKind::Assignment) {
-                    Some(_) => parse_value(parser, diagnostics)?,
+                    Some(_) => {
+                        parser.next();
+                        parse_value(parser, diagnostics)?
+                    }
                     None => BlockValue::Empty,
                 }
             ))
         },
-        TokenValue::Comma => Ok(KeyValueEntry::new(
-            Key::MacroValue(identifier),
-            start_loc,
-            BlockValue::Empty)),
         TokenValue::Assignment => Ok(KeyValueEntry::new(
             Key::Name(identifier),
             start_loc,
             parse_value(parser, diagnostics)?)),
-        _ => return Err(Error::UnexpectedToken((*next).clone()))
+        TokenValue::Append => Ok(KeyValueEntry::new_append(
+            Key::Name(identifier),
+            start_loc,
+            parse_value(parser, diagnostics)?)),
+        _ => Err(unexpected(next, OPERATOR))
     }
 }
 
-fn parse_macro_arguments(parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<String>> {
+fn parse_macro_arguments(parser: &mut Parser) -> Result<Vec<String>> {
     let mut result = vec![];
 
     while let Some(token) = parser.peek() {
@@ -169,9 +371,9 @@ fn parse_macro_arguments(parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>)
                         return Ok(result)
                     } else if kind == TokenKind::Comma {
                         parser.next();
-                    } else { return Err(Error::UnexpectedToken((*next).clone())) }
+                    } else { return Err(unexpected(next, &[TokenKind::Comma, TokenKind::CloseParen])) }
                 } else {
-                    return Err(Error::UnexpectedEOF)
+                    return Err(parser.eof(&[TokenKind::Comma, TokenKind::CloseParen]))
                 }
             }
             TokenValue::IntegerLiteral(n) => {
@@ -185,19 +387,19 @@ fn parse_macro_arguments(parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>)
                         return Ok(result)
                     } else if kind == TokenKind::Comma {
                         parser.next();
-                    }
+                    } else { return Err(unexpected(next, &[TokenKind::Comma, TokenKind::CloseParen])) }
                 } else {
-                    return Err(Error::UnexpectedEOF)
+                    return Err(parser.eof(&[TokenKind::Comma, TokenKind::CloseParen]))
                 }
             }
             TokenValue::CloseParen => { parser.next(); break },
-            _ => return Err(Error::UnexpectedToken((*token).clone()))
+            _ => return Err(unexpected(token, &[TokenKind::Identifier, TokenKind::IntegerLiteral, TokenKind::CloseParen]))
         }
     }
     Ok(result)
 }
 
-fn parse_macro_parameters(parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>) -> Result<Vec<String>> {
+fn parse_macro_parameters(parser: &mut Parser) -> Result<Vec<String>> {
     let mut result = vec![];
 
     while let Some(token) = parser.peek() {
@@ -214,42 +416,34 @@ fn parse_macro_parameters(parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>
                         return Ok(result)
                     } else if kind == TokenKind::Comma {
                         parser.next();
-                    } else { return Err(Error::UnexpectedToken((*next).clone())) }
+                    } else { return Err(unexpected(next, &[TokenKind::Comma, TokenKind::CloseParen])) }
                 } else {
-                    return Err(Error::UnexpectedEOF)
+                    return Err(parser.eof(&[TokenKind::Comma, TokenKind::CloseParen]))
                 }
 
             }
             TokenValue::CloseParen => { parser.next(); break },
-            _ => return Err(Error::UnexpectedToken((*token).clone()))
+            _ => return Err(unexpected(token, &[TokenKind::MacroParameter, TokenKind::CloseParen]))
         }
     }
     Ok(result)
 }
 
 fn parse_macro_definition(start: &Token, parser: &mut Parser, diagnostics: &mut Vec<Diagnostic>) -> Result<KeyValueEntry> {
-    let location = start.source_loc.clone();
+    let location = start.source_loc;
     let name = parser.expect_identifier()?;
 
-    let next = parser.next().ok_or(Error::UnexpectedEOF)?;
-
-    let args = if next.token_val.kind() == TokenKind::OpenParen {
-        parse_macro_parameters(parser, diagnostics)?
-    } else {
-        vec![]
+    let args = match parser.peek().map(|t| t.token_val.kind()) {
+        Some(TokenKind::OpenParen) => {
+            parser.next();
+            parse_macro_parameters(parser)?
+        }
+        _ => vec![],
     };
 
     parser.expect(TokenKind::Assignment)?;
 
-    let next = parser.next().ok_or(Error::UnexpectedEOF)?;
-
-    let value = match &next.token_val {
-        TokenValue::OpenBrace => Ok(BlockValue::Block(parse_block(parser, diagnostics)?)),
-        TokenValue::IntegerLiteral(n) => Ok(BlockValue::Literal(n.to_string())),
-        TokenValue::String(s) => Ok(BlockValue::Literal(s.to_string())),
-        TokenValue::MacroParameter(p) => Ok(BlockValue::MacroValue(p.to_string())),
-        _ => return Err(Error::UnexpectedToken((*next).clone()))
-    }?;
+    let value = parse_value(parser, diagnostics)?;
 
     Ok(KeyValueEntry::new(
         Key::MacroSignature { name, args },
@@ -273,50 +467,87 @@ fn parse_macro_call(start: &Token, parser: &mut Parser, diagnostics: &mut Vec<Di
 
     let location = start.source_loc;
 
-    let next = parser.next().ok_or(Error::UnexpectedEOF)?;
-
-
-    let args = match next.token_val.kind() {
-        TokenKind::OpenParen => parse_macro_arguments(parser, diagnostics)?,
-        _ => { unimplemented!(); },
+    let args = match parser.peek().map(|t| t.token_val.kind()) {
+        Some(TokenKind::OpenParen) => {
+            parser.next();
+            parse_macro_arguments(parser)?
+        }
+        _ => vec![],
     };
 
     parser.expect(TokenKind::Assignment)?;
 
     let value = parse_value(parser, diagnostics)?;
     
-    Ok(KeyValueEntry::new(Key::MacroSignature { name, args }, location, value)) 
+    Ok(KeyValueEntry::new(Key::MacroCall { name, args }, location, value))
 }
 
+/// `include "path"` or `import "path"`. They are only directives when a
+/// string follows, so either word can still be used as a key.
+fn parse_directive(keyword: &str, start: &Token, parser: &mut Parser) -> Option<KeyValueEntry> {
+    let Some(TokenValue::String(path)) = parser.peek().map(|t| &t.token_val) else {
+        return None;
+    };
+    let key = match keyword {
+        "include" => Key::Include(path.to_string()),
+        "import" => Key::Import(path.to_string()),
+        _ => return None,
+    };
+    parser.next();
+    Some(KeyValueEntry::new(key, start.source_loc, BlockValue::Empty))
+}
 
+/// Parses a document. Syntax errors are recorded in `diagnostics` and the
+/// parser skips ahead to the next entry, so the returned block holds every
+/// entry that could be read.
 pub fn parse(tokens: &[Token], diagnostics: &mut Vec<Diagnostic>) -> Result<KeyValueBlock> {
-    use crate::token::TokenValue;
     let mut parser = Parser::new(tokens);
     let mut entries = KeyValueBlock::new();
 
     while let Some(token) = parser.next() {
-        let token = (*token).clone();
-        let tmp = match &token.token_val {
-            TokenValue::Identifier(s) => {
-                parse_identifier_key(s.to_string(), token.source_loc, &mut parser, diagnostics)?
+        let result = match &token.token_val {
+            TokenValue::Identifier(s) => match parse_directive(s, token, &mut parser) {
+                Some(entry) => Ok(entry),
+                None => parse_identifier_key(s.to_string(), token.source_loc, &mut parser, diagnostics),
             },
             TokenValue::Macro => {
-                parse_macro_definition(&token, &mut parser, diagnostics)?
+                parse_macro_definition(token, &mut parser, diagnostics)
             },
-            TokenValue::MacroCall(p) => {
-                let result = parse_macro_call(&token, &mut parser, diagnostics)?;
-                result
+            TokenValue::MacroCall(_) => {
+                parse_macro_call(token, &mut parser, diagnostics)
             },
-            _ => return Err(Error::UnexpectedToken(token.clone()))
+            _ => Err(unexpected(token, TOP_LEVEL_KEY))
         };
-        entries.add(tmp);
+        match result {
+            Ok(entry) => entries.add(parser.finish_entry(entry, token)),
+            Err(e) => {
+                report(&parser, diagnostics, e);
+                recover_top_level(&mut parser);
+            }
+        }
     }
+    entries.comments = parser.comments.remove(&END_OF_INPUT).unwrap_or_default();
     Ok(entries)
 }
 
+/// Parses a standalone value, as it would appear on the right hand side of `=`.
+pub fn parse_single_value(tokens: &[Token], diagnostics: &mut Vec<Diagnostic>) -> Result<BlockValue> {
+    let mut parser = Parser::new(tokens);
+    let value = parse_value(&mut parser, diagnostics)?;
+    match parser.next() {
+        Some(token) => Err(unexpected(token, &[TokenKind::Eof])),
+        None => Ok(value),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{diagnostic::Diagnostic, token::{SourceLoc, Token, TokenValue}};
+    use crate::{
+        diagnostic::DiagnosticCategory,
+        keyvalue::{BlockValue, Key},
+        lexer::lex,
+        token::{SourceLoc, Token, TokenValue},
+    };
 
     use super::parse;
 
@@ -367,10 +598,53 @@ mod tests {
             println!("{:?}", entry);
         }
     }
-}
 
+    #[test]
+    fn recovers_from_errors() {
+        let text = "\
+Quotes = { id = 1, type = = \"Table\", name = \"q\" }
+Broken = ,
+Machines = { id = 2, fields = { a = } }
+Last = 3
+";
+        let mut diagnostics = vec![];
+        let tokens = lex(text, &mut diagnostics).unwrap();
+        let store = parse(&tokens, &mut diagnostics).unwrap();
 
+        let categories: Vec<_> = diagnostics.iter().map(|d| d.category()).collect();
+        assert_eq!(categories, vec![DiagnosticCategory::UnexpectedToken; 3]);
+        assert_eq!(diagnostics[0].location(), SourceLoc::new(1, 27));
+        assert_eq!(diagnostics[1].location(), SourceLoc::new(2, 10));
 
+        let keys: Vec<_> = store.entries.iter().map(|e| e.key().to_string()).collect();
+        assert_eq!(keys, vec!["Quotes", "Machines", "Last"]);
+        let Some(BlockValue::Block(quotes)) = store.get(Key::Name("Quotes".into())) else { panic!() };
+        assert_eq!(quotes.entries.len(), 2);
+        assert_eq!(store.get(Key::Name("Last".into())), Some(&BlockValue::Integer(3)));
+    }
 
+    #[test]
+    fn reports_unclosed_blocks() {
+        let mut diagnostics = vec![];
+        let tokens = lex("Quotes = { id = 1", &mut diagnostics).unwrap();
+        let store = parse(&tokens, &mut diagnostics).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].category(), DiagnosticCategory::UnclosedBlock);
+        assert_eq!(store.entries.len(), 1);
+    }
 
+    #[test]
+    fn errors_name_expected_tokens() {
+        let mut diagnostics = vec![];
+        let tokens = lex("a = {\n  b = ", &mut diagnostics).unwrap();
+        parse(&tokens, &mut diagnostics).unwrap();
+        assert_eq!(diagnostics[0].category(), DiagnosticCategory::UnexpectedEOF);
+        assert_eq!(diagnostics[0].location(), SourceLoc::new(2, 6));
+        assert!(diagnostics[0].message().ends_with("expected one of `{`, an integer, a float, a string, a macro parameter or a reference"));
 
+        let mut diagnostics = vec![];
+        let tokens = lex("a = { b = 1 c = 2 }", &mut diagnostics).unwrap();
+        parse(&tokens, &mut diagnostics).unwrap();
+        assert_eq!(diagnostics[0].message(), "unexpected `c`, expected `,` or `}`");
+    }
+}
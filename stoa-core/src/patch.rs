@@ -0,0 +1,381 @@
+//! Patches: a list of edits at key paths, which can be saved and applied to
+//! a document later, such as a schema migration. A patch is itself written
+//! in stoa, one entry per edit, applied in order:
+//!
+//! ```text
+//! rename = { path = "Machines", to = "Devices" }
+//! replace = { path = "Quotes.fields.total.type", old = "Number", new = "Integer" }
+//! add = { path = "Quotes.fields.made", value = { type = "Date" } }
+//! remove = { path = "Notes", old = { id = 3, type = "Table" } }
+//! ```
+//!
+//! Removals and replacements carry the value they expect to find, so a patch
+//! applied to a document that has moved on reports a conflict instead of
+//! overwriting it. Leaving out `value`, `old` or `new` stands for a bare
+//! name, such as `required`.
+
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCategory},
+    diff::{same, Change},
+    error::{Error, Result},
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry},
+    printer::{self, value_to_source},
+    query,
+    token::{SourceLoc, Span},
+    validate,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "op", rename_all = "snake_case"))]
+pub enum Edit {
+    Add { path: Vec<String>, value: BlockValue },
+    Remove { path: Vec<String>, old: BlockValue },
+    Replace { path: Vec<String>, old: BlockValue, new: BlockValue },
+    /// Gives the entry at `path` another key, keeping its place.
+    Rename { path: Vec<String>, to: String },
+}
+
+impl Edit {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Add { .. } => "add",
+            Self::Remove { .. } => "remove",
+            Self::Replace { .. } => "replace",
+            Self::Rename { .. } => "rename",
+        }
+    }
+
+    pub fn path(&self) -> &[String] {
+        match self {
+            Self::Add { path, .. } | Self::Remove { path, .. } | Self::Replace { path, .. } | Self::Rename { path, .. } => path,
+        }
+    }
+}
+
+/// An edit, along with where it was written in its patch.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Operation {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub edit: Edit,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub location: SourceLoc,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Patch {
+    pub operations: Vec<Operation>,
+}
+
+impl Patch {
+    /// The patch that makes the changes found by [`diff`](crate::diff::diff).
+    pub fn from_changes(changes: Vec<Change>) -> Self {
+        let operations = changes
+            .into_iter()
+            .map(|change| match change {
+                Change::Added { path, value } => Edit::Add { path, value },
+                Change::Removed { path, value } => Edit::Remove { path, old: value },
+                Change::Changed { path, old, new } => Edit::Replace { path, old, new },
+                Change::Renamed { from, to } => Edit::Rename { path: vec![from], to },
+            })
+            .map(|edit| Operation { edit, location: SourceLoc::new(0, 0), span: Span::default() })
+            .collect();
+        Self { operations }
+    }
+
+    /// Reads a patch written in stoa, as described in the module docs.
+    pub fn from_block(patch: &KeyValueBlock) -> Result<Self> {
+        let mut operations = vec![];
+        for entry in &patch.entries {
+            let name = entry.key().to_string();
+            let invalid = |message: String| Error::InvalidPatch(message, entry.location());
+            if !matches!(entry.value(), BlockValue::Block(_)) {
+                return Err(invalid(format!("`{name}` must be a block")));
+            }
+            let string = |key: &str| match validate::value(entry, key).map(KeyValueEntry::value) {
+                Some(BlockValue::Literal(s)) => Ok(s.clone()),
+                _ => Err(invalid(format!("`{name}` needs a `{key}` string"))),
+            };
+            let value = |key: &str| validate::value(entry, key).map_or(BlockValue::Empty, |e| e.value().clone());
+            let path = string("path")?;
+            let path = query::parse_key_path(&path).ok_or_else(|| invalid(format!("`{path}` is not a key path")))?;
+
+            let edit = match name.as_str() {
+                "add" => Edit::Add { path, value: value("value") },
+                "remove" => Edit::Remove { path, old: value("old") },
+                "replace" => Edit::Replace { path, old: value("old"), new: value("new") },
+                "rename" => Edit::Rename { path, to: string("to")? },
+                _ => return Err(invalid(format!("unknown edit `{name}`, expected add, remove, replace or rename"))),
+            };
+            operations.push(Operation { edit, location: entry.location(), span: entry.span() });
+        }
+        Ok(Self { operations })
+    }
+
+    /// Writes the patch in stoa, for [`Patch::from_block`] to read back.
+    pub fn to_source(&self) -> String {
+        let entry = |key: &str, value| KeyValueEntry::new(Key::Name(key.to_string()), SourceLoc::new(0, 0), value);
+        let mut patch = KeyValueBlock::new();
+        for operation in &self.operations {
+            let edit = &operation.edit;
+            let mut fields = vec![entry("path", BlockValue::Literal(edit.path().join(".")))];
+            let values = match edit {
+                Edit::Add { value, .. } => vec![("value", value)],
+                Edit::Remove { old, .. } => vec![("old", old)],
+                Edit::Replace { old, new, .. } => vec![("old", old), ("new", new)],
+                Edit::Rename { to, .. } => {
+                    fields.push(entry("to", BlockValue::Literal(to.clone())));
+                    vec![]
+                }
+            };
+            for (key, value) in values.into_iter().filter(|(_, v)| **v != BlockValue::Empty) {
+                fields.push(entry(key, value.clone()));
+            }
+            patch.add(entry(edit.name(), BlockValue::Block(KeyValueBlock { entries: fields, ..KeyValueBlock::new() })));
+        }
+        printer::to_source(&patch)
+    }
+
+    /// Applies every edit in order. An edit that finds something other than
+    /// it expects is a conflict, reported at the edit; if there are any, the
+    /// document is left as it was.
+    pub fn apply(&self, document: &mut KeyValueBlock) -> Vec<Diagnostic> {
+        let mut patched = document.clone();
+        let mut conflicts = vec![];
+        for operation in &self.operations {
+            if let Err(conflict) = apply(&mut patched, &operation.edit) {
+                let diagnostic = Diagnostic::new(DiagnosticCategory::PatchConflict, operation.location, String::new())
+                    .with_span(operation.span)
+                    .with_message(conflict.message);
+                let diagnostic = match conflict.note {
+                    Some(note) => diagnostic.with_note(note),
+                    None => diagnostic,
+                };
+                conflicts.push(match conflict.found {
+                    Some((location, span)) => diagnostic.with_related(location, span, "found here"),
+                    None => diagnostic,
+                });
+            }
+        }
+        if conflicts.is_empty() {
+            *document = patched;
+        }
+        conflicts
+    }
+}
+
+struct Conflict {
+    message: String,
+    note: Option<String>,
+    /// The entry that was in the way.
+    found: Option<(SourceLoc, Span)>,
+}
+
+impl Conflict {
+    fn new(message: String) -> Self {
+        Self { message, note: None, found: None }
+    }
+
+    fn at(self, entry: &KeyValueEntry) -> Self {
+        Self { found: Some((entry.location(), entry.span())), ..self }
+    }
+}
+
+fn apply(document: &mut KeyValueBlock, edit: &Edit) -> std::result::Result<(), Conflict> {
+    let path = edit.path();
+    let shown = path.join(".");
+    let cannot = |what: &str| format!("cannot {} `{shown}`: {what}", edit.name());
+    let Some((last, parents)) = path.split_last() else {
+        return Err(Conflict::new(cannot("the path is empty")));
+    };
+
+    let mut block = document;
+    for (i, segment) in parents.iter().enumerate() {
+        let parent = path[..=i].join(".");
+        let entry = position(block, segment)
+            .map(|index| &mut block.entries[index])
+            .ok_or_else(|| Conflict::new(cannot(&format!("`{parent}` does not exist"))))?;
+        let found = (entry.location(), entry.span());
+        block = match entry.value_mut() {
+            BlockValue::Block(inner) => inner,
+            _ => return Err(Conflict { found: Some(found), ..Conflict::new(cannot(&format!("`{parent}` is not a block"))) }),
+        };
+    }
+
+    let index = position(block, last);
+    match (edit, index) {
+        (Edit::Add { value, .. }, None) => {
+            block.add(KeyValueEntry::new(key(last, value), SourceLoc::new(0, 0), value.clone()));
+            Ok(())
+        }
+        (Edit::Add { .. }, Some(index)) => Err(Conflict::new(cannot("it already exists")).at(&block.entries[index])),
+        (Edit::Rename { to, .. }, Some(index)) => match position(block, to) {
+            Some(other) => Err(Conflict::new(cannot(&format!("`{to}` already exists"))).at(&block.entries[other])),
+            None => {
+                let entry = &block.entries[index];
+                block.entries[index] = KeyValueEntry::new(key(to, entry.value()), entry.location(), entry.value().clone())
+                    .with_span(entry.span(), entry.value_span())
+                    .with_comments(entry.comments().to_vec())
+                    .with_trailing_comment(entry.trailing_comment().map(str::to_string));
+                Ok(())
+            }
+        },
+        (Edit::Remove { old, .. } | Edit::Replace { old, .. }, Some(index)) => {
+            let entry = &block.entries[index];
+            if !same(entry.value(), old) {
+                let note = match (old, entry.value()) {
+                    (BlockValue::Block(_), _) | (_, BlockValue::Block(_)) => None,
+                    (old, found) => Some(format!("expected {}, found {}", shown_value(old), shown_value(found))),
+                };
+                return Err(Conflict { note, ..Conflict::new(cannot("it has changed")).at(entry) });
+            }
+            match edit {
+                Edit::Replace { new, .. } => *block.entries[index].value_mut() = new.clone(),
+                _ => {
+                    block.entries.remove(index);
+                }
+            }
+            Ok(())
+        }
+        (_, None) => Err(Conflict::new(cannot("it does not exist"))),
+    }
+}
+
+/// The last entry of `block` whose key reads as `segment`, as in the paths
+/// [`diff`](crate::diff::diff) reports.
+fn position(block: &KeyValueBlock, segment: &str) -> Option<usize> {
+    block.entries.iter().rposition(|e| e.key().to_string() == segment)
+}
+
+/// The key a path segment reads as, the reverse of how keys are displayed.
+/// A plain name without a value is bare.
+fn key(segment: &str, value: &BlockValue) -> Key {
+    if let Some(name) = segment.strip_prefix('$') {
+        return Key::MacroValue(name.to_string());
+    }
+    let call = segment.strip_prefix('@').and_then(|call| call.strip_suffix(')')?.split_once('('));
+    match call {
+        Some((name, "")) => Key::MacroCall { name: name.to_string(), args: vec![] },
+        Some((name, args)) => {
+            Key::MacroCall { name: name.to_string(), args: args.split(',').map(|arg| arg.trim().to_string()).collect() }
+        }
+        None if *value == BlockValue::Empty => Key::Bare(segment.to_string()),
+        None => Key::Name(segment.to_string()),
+    }
+}
+
+fn shown_value(value: &BlockValue) -> String {
+    match value {
+        BlockValue::Empty => "a bare name".to_string(),
+        value => format!("`{}`", value_to_source(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        diff::diff,
+        keyvalue::KeyValueBlock,
+        printer::to_source,
+        testing::{document, document_in},
+        token::SourceLoc,
+    };
+
+    use super::Patch;
+
+    const OLD: &str = "\
+Quotes = { id = 1, type = \"Table\", fields = { title = { type = \"Text\" }, total = { type = \"Number\" } } }
+Machines = { id = 2, type = \"Table\" }
+Notes = { id = 3, type = \"Table\" }
+";
+    const NEW: &str = "\
+Quotes = { id = 1, type = \"Table\", fields = { title = { type = \"Text\", required }, total = { type = \"Integer\" } } }
+Devices = { id = 2, type = \"Table\" }
+";
+
+    #[test]
+    fn round_trips_through_stoa() {
+        let patch = Patch::from_changes(diff(&document(OLD), &document(NEW)));
+        let source = patch.to_source();
+        assert_eq!(
+            source,
+            "\
+add = {
+  path = \"Quotes.fields.title.required\"
+}
+
+replace = {
+  path = \"Quotes.fields.total.type\",
+  old = \"Number\",
+  new = \"Integer\"
+}
+
+remove = {
+  path = \"Notes\",
+  old = {
+    id = 3,
+    type = \"Table\"
+  }
+}
+
+rename = {
+  path = \"Machines\",
+  to = \"Devices\"
+}
+"
+        );
+        let read = Patch::from_block(&document(&source)).unwrap();
+        assert_eq!(read.to_source(), source);
+
+        let mut patched = document(OLD);
+        assert!(read.apply(&mut patched).is_empty());
+        assert!(diff(&patched, &document(NEW)).is_empty());
+        assert_eq!(to_source(&patched).lines().nth(6), Some("      required"));
+
+        let error = Patch::from_block(&document("move = { path = \"A\" }")).unwrap_err();
+        assert_eq!(error.to_string(), "1:1: unknown edit `move`, expected add, remove, replace or rename");
+    }
+
+    #[test]
+    fn adds_macro_calls() {
+        let old = document("@table(1, Quotes) = {}\n");
+        let new = document("@table(1, Quotes) = { extra }\n@table(2, Lines) = {}\n@empty() = {}\n");
+        let mut patched = old.clone();
+        assert!(Patch::from_changes(diff(&old, &new)).apply(&mut patched).is_empty());
+        let keys = |document: &KeyValueBlock| document.entries.iter().map(|e| e.key().clone()).collect::<Vec<_>>();
+        assert_eq!(keys(&patched), keys(&new));
+        assert!(diff(&patched, &new).is_empty());
+    }
+
+    #[test]
+    fn reports_conflicts() {
+        let text = "\
+replace = { path = \"Quotes.fields.total.type\", old = \"Number\", new = \"Integer\" }
+add = { path = \"Machines\", value = {} }
+remove = { path = \"Parts.fields\" }
+";
+        let patch = Patch::from_block(&document_in(text, 1)).unwrap();
+
+        let original = document("Quotes = { fields = { total = { type = \"Text\" } } }\nMachines = {}\n");
+        let mut target = original.clone();
+        let conflicts = patch.apply(&mut target);
+        assert_eq!(target, original);
+
+        let messages = conflicts.iter().map(|d| d.message()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "cannot replace `Quotes.fields.total.type`: it has changed",
+                "cannot add `Machines`: it already exists",
+                "cannot remove `Parts.fields`: `Parts` does not exist",
+            ]
+        );
+        assert_eq!(conflicts[0].location(), SourceLoc::new(1, 1).with_file(1));
+        assert_eq!(conflicts[0].notes(), ["expected `\"Number\"`, found `\"Text\"`"]);
+        assert_eq!(conflicts[1].related()[0].location, SourceLoc::new(2, 1));
+    }
+}
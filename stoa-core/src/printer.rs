@@ -0,0 +1,181 @@
+use std::fmt::Write;
+
+use crate::keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry, Operator};
+
+const INDENT: usize = 2;
+
+/// Renders a document back to stoa source in the canonical layout: top level
+/// entries separated by a blank line, nested entries one per line with
+/// trailing commas between them.
+pub fn to_source(block: &KeyValueBlock) -> String {
+    let mut out = String::new();
+    for (i, entry) in block.entries.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        write_entry(&mut out, entry, 0);
+        write_trailing_comment(&mut out, entry);
+        out.push('\n');
+    }
+    if !block.comments.is_empty() && !block.entries.is_empty() {
+        out.push('\n');
+    }
+    write_comments(&mut out, &block.comments, 0);
+    out
+}
+
+/// Renders a single value as it would appear on the right hand side of `=`.
+pub fn value_to_source(value: &BlockValue) -> String {
+    value_to_source_at(value, 0)
+}
+
+/// Renders a value whose entry starts `indent` columns in, so the lines of a
+/// nested block line up with their surroundings.
+pub fn value_to_source_at(value: &BlockValue, indent: usize) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value, indent);
+    out
+}
+
+/// Renders a single entry without leading indentation.
+pub fn entry_to_source_at(entry: &KeyValueEntry, indent: usize) -> String {
+    entry_to_source_with(entry, indent, "")
+}
+
+/// Like [`entry_to_source_at`], with `separator`, such as a comma, written
+/// before the entry's trailing comment.
+pub fn entry_to_source_with(entry: &KeyValueEntry, indent: usize, separator: &str) -> String {
+    let mut out = String::new();
+    write_entry(&mut out, entry, indent);
+    out.push_str(separator);
+    write_trailing_comment(&mut out, entry);
+    out.trim_start().to_string()
+}
+
+fn write_comments(out: &mut String, comments: &[String], indent: usize) {
+    for comment in comments {
+        let _ = writeln!(out, "{:indent$}#{}", "", comment, indent = indent);
+    }
+}
+
+fn write_trailing_comment(out: &mut String, entry: &KeyValueEntry) {
+    if let Some(comment) = entry.trailing_comment() {
+        let _ = write!(out, " #{comment}");
+    }
+}
+
+/// Writes an entry up to the end of its value, leaving its trailing comment
+/// to go after the separator.
+fn write_entry(out: &mut String, entry: &KeyValueEntry, indent: usize) {
+    write_comments(out, entry.comments(), indent);
+    if *entry.value() == BlockValue::Empty {
+        let _ = write!(out, "{:indent$}{}", "", entry.key(), indent = indent);
+        return;
+    }
+    let _ = write!(out, "{:indent$}{}", "", entry.key(), indent = indent);
+    out.push_str(match entry.operator() {
+        Operator::Assign => " = ",
+        Operator::Append => " += ",
+    });
+    write_value(out, entry.value(), indent);
+}
+
+fn write_value(out: &mut String, value: &BlockValue, indent: usize) {
+    let _ = match value {
+        BlockValue::Literal(s) => write!(out, "\"{}\"", s),
+        BlockValue::Integer(n) => write!(out, "{}", n),
+        // Debug keeps the decimal point so the value lexes back as a float.
+        BlockValue::Float(n) => write!(out, "{:?}", n),
+        BlockValue::Expression(e) => write!(out, "{}", e),
+        BlockValue::MacroValue(p) => write!(out, "{}", Key::MacroValue(p.clone())),
+        BlockValue::Reference(path) => write!(out, "&{}", path.join(".")),
+        BlockValue::Empty => Ok(()),
+        BlockValue::Block(block) => {
+            write_block(out, block, indent);
+            Ok(())
+        }
+    };
+}
+
+fn write_block(out: &mut String, block: &KeyValueBlock, indent: usize) {
+    if block.entries.is_empty() && block.comments.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+    out.push_str("{\n");
+    for (i, entry) in block.entries.iter().enumerate() {
+        write_entry(out, entry, indent + INDENT);
+        if i + 1 < block.entries.len() {
+            out.push(',');
+        }
+        write_trailing_comment(out, entry);
+        out.push('\n');
+    }
+    write_comments(out, &block.comments, indent + INDENT);
+    let _ = write!(out, "{:indent$}}}", "", indent = indent);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lexer::lex, parser::parse};
+
+    use super::to_source;
+
+    #[test]
+    fn round_trip() {
+        let text = "Quotes = {\n  id = 1,\n  type = \"Table\",\n  fields = {}\n}\n\nMachines = {\n  id = 2\n}\n";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        let store = parse(&tokens, &mut diags).unwrap();
+        assert_eq!(to_source(&store), text);
+    }
+
+    #[test]
+    fn comments() {
+        let text = "# Tables\nQuotes = {\n  # primary key\n  id = 1,\n  fields = {\n    # none yet\n  }\n}\n\n# end\n";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        let store = parse(&tokens, &mut diags).unwrap();
+        assert_eq!(to_source(&store), text);
+    }
+
+    #[test]
+    fn trailing_comments() {
+        let text = "Machines = {\n  id = 2, # trailing\n  type = \"Table\" # last\n} # after\n\nNotes = 1 # end\n";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        let store = parse(&tokens, &mut diags).unwrap();
+        assert_eq!(to_source(&store), text);
+
+        let text = "Quotes = { # about quotes\n  id = 1, # one\n  # leading\n  fields = {} # none\n}\n";
+        let tokens = lex(text, &mut diags).unwrap();
+        let store = parse(&tokens, &mut diags).unwrap();
+        assert_eq!(
+            to_source(&store),
+            "Quotes = {\n  # about quotes\n  id = 1, # one\n  # leading\n  fields = {} # none\n}\n"
+        );
+    }
+
+    #[test]
+    fn macros() {
+        let text = "macro table($id, $name) = {\n  $name = {\n    id = $id,\n    $body\n  }\n}\n\n@table(1, Quotes) = {\n  ratio = 1.5\n}\n\nQuotes += {\n  fields = {}\n}\n";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        let store = parse(&tokens, &mut diags).unwrap();
+        assert_eq!(to_source(&store), text);
+    }
+
+    #[test]
+    fn bare_names() {
+        let text = "types = {
+  Table Occurrence,
+  required,
+  $Layout
+}
+";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        let store = parse(&tokens, &mut diags).unwrap();
+        assert_eq!(to_source(&store), text);
+    }
+}
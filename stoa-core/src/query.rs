@@ -1 +1,78 @@
+use crate::keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry};
+
 pub type KeyPath = [String];
+
+/// Splits a dotted query such as `Quotes Machines.table` into its segments.
+/// Whitespace inside a segment is normalised the same way the parser joins
+/// multi-word keys. Returns `None` if any segment is empty.
+pub fn parse_key_path(query: &str) -> Option<Vec<String>> {
+    query
+        .split('.')
+        .map(|segment| {
+            let segment = segment.split_whitespace().collect::<Vec<_>>().join(" ");
+            (!segment.is_empty()).then_some(segment)
+        })
+        .collect()
+}
+
+pub fn get<'a>(block: &'a KeyValueBlock, path: &KeyPath) -> Option<&'a BlockValue> {
+    get_entry(block, path).map(KeyValueEntry::value)
+}
+
+/// Finds the entry at `path`, taking the first match at each level.
+pub fn get_entry<'a>(block: &'a KeyValueBlock, path: &KeyPath) -> Option<&'a KeyValueEntry> {
+    let (first, rest) = path.split_first()?;
+    let key = Key::Name(first.clone());
+    let entry = block.entries.iter().find(|e| *e.key() == key)?;
+    if rest.is_empty() {
+        return Some(entry);
+    }
+    match entry.value() {
+        BlockValue::Block(inner) => get_entry(inner, rest),
+        _ => None,
+    }
+}
+
+pub fn get_mut<'a>(block: &'a mut KeyValueBlock, path: &KeyPath) -> Option<&'a mut BlockValue> {
+    let (first, rest) = path.split_first()?;
+    let value = block.get_mut(Key::Name(first.clone()))?;
+    if rest.is_empty() {
+        return Some(value);
+    }
+    match value {
+        BlockValue::Block(inner) => get_mut(inner, rest),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{keyvalue::BlockValue, lexer::lex, parser::parse};
+
+    use super::{get, parse_key_path};
+
+    #[test]
+    fn key_paths() {
+        assert_eq!(
+            parse_key_path("Quotes  Machines .table"),
+            Some(vec!["Quotes Machines".to_string(), "table".to_string()])
+        );
+        assert_eq!(parse_key_path("Quotes..id"), None);
+        assert_eq!(parse_key_path(""), None);
+    }
+
+    #[test]
+    fn nested_lookup() {
+        let text = "Quotes Machines = { id = 1, table = \"Machines\", fields = { a = 2 } }";
+        let mut diags = vec![];
+        let tokens = lex(text, &mut diags).unwrap();
+        let store = parse(&tokens, &mut diags).unwrap();
+
+        let path = parse_key_path("Quotes Machines.fields.a").unwrap();
+        assert_eq!(get(&store, &path), Some(&BlockValue::Integer(2)));
+        let path = parse_key_path("Quotes Machines.table").unwrap();
+        assert_eq!(get(&store, &path), Some(&BlockValue::Literal("Machines".to_string())));
+        let path = parse_key_path("Quotes Machines.id.x").unwrap();
+        assert_eq!(get(&store, &path), None);
+    }
+}
@@ -0,0 +1,513 @@
+//! Schemas describe the expected shape of a document, and are written in
+//! stoa themselves. Each top-level entry of a schema document names a schema:
+//!
+//! ```text
+//! Table = {
+//!   when = { type = "Table" },
+//!   fields = {
+//!     id = { type = "integer", min = 1, required },
+//!     type = { one_of = { Table, Table Occurrence }, required },
+//!     fields = { type = "block", each = "Field" }
+//!   }
+//! }
+//!
+//! Field = {
+//!   closed,
+//!   fields = { type = { type = "string" } }
+//! }
+//! ```
+//!
+//! A schema with a `when` block applies to every top-level entry of a
+//! document whose block contains those keys with those values. Without one,
+//! it only applies where another schema refers to it. `closed` rejects keys
+//! that are not listed in `fields`.
+//!
+//! Each field may give:
+//!
+//! - `type`: `"string"`, `"integer"`, `"float"`, `"number"` (an integer or a
+//!   float), `"block"` or `"any"`.
+//! - `required`, to report the key when it is missing.
+//! - `one_of`: the values allowed. Entries are either bare names, which stand
+//!   for the string of that name, or `key = value`, where only the value
+//!   counts.
+//! - `min` and `max`: an inclusive range for numbers.
+//! - `schema`: a schema for a block value, by name or inline.
+//! - `each`: a schema for every entry of a block value, by name or inline.
+
+use std::collections::HashMap;
+
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCategory},
+    error::{Error, Result},
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry},
+    token::{SourceLoc, Span},
+};
+
+/// Every schema read from a schema document.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    shapes: HashMap<String, Shape>,
+    /// The names of the schemas, in the order they were defined.
+    order: Vec<String>,
+}
+
+/// The expected contents of a block.
+#[derive(Debug, Clone)]
+struct Shape {
+    name: String,
+    location: SourceLoc,
+    span: Span,
+    when: Vec<(String, BlockValue)>,
+    closed: bool,
+    fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    location: SourceLoc,
+    span: Span,
+    kind: ValueKind,
+    required: bool,
+    one_of: Vec<BlockValue>,
+    min: Option<f64>,
+    max: Option<f64>,
+    schema: Option<ShapeRef>,
+    each: Option<ShapeRef>,
+}
+
+#[derive(Debug, Clone)]
+enum ShapeRef {
+    Named(String, SourceLoc),
+    Inline(Box<Shape>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueKind {
+    String,
+    Integer,
+    Float,
+    Number,
+    Block,
+    Any,
+}
+
+impl ValueKind {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "string" => Self::String,
+            "integer" => Self::Integer,
+            "float" => Self::Float,
+            "number" => Self::Number,
+            "block" => Self::Block,
+            "any" => Self::Any,
+            _ => return None,
+        })
+    }
+
+    fn accepts(self, value: &BlockValue) -> bool {
+        matches!(
+            (self, value),
+            (Self::Any, _)
+                | (Self::String, BlockValue::Literal(_))
+                | (Self::Integer, BlockValue::Integer(_))
+                | (Self::Float, BlockValue::Float(_))
+                | (Self::Number, BlockValue::Integer(_) | BlockValue::Float(_))
+                | (Self::Block, BlockValue::Block(_))
+        )
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::String => "a string",
+            Self::Integer => "an integer",
+            Self::Float => "a float",
+            Self::Number => "a number",
+            Self::Block => "a block",
+            Self::Any => "anything",
+        }
+    }
+}
+
+fn number(value: &BlockValue) -> Option<f64> {
+    match value {
+        BlockValue::Integer(n) => Some(*n as f64),
+        BlockValue::Float(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Formats a value as it would be written in stoa.
+fn source(value: &BlockValue) -> String {
+    match value {
+        BlockValue::Literal(s) => format!("\"{s}\""),
+        BlockValue::Integer(n) => n.to_string(),
+        BlockValue::Float(n) => format!("{n:?}"),
+        BlockValue::Expression(e) => e.clone(),
+        BlockValue::Block(_) => "{ ... }".to_string(),
+        BlockValue::MacroValue(p) => format!("${p}"),
+        BlockValue::Reference(path) => format!("&{}", path.join(".")),
+        BlockValue::Empty => String::new(),
+    }
+}
+
+/// A bare `name` in a schema, such as `required`.
+fn flag(entry: &KeyValueEntry) -> Option<&str> {
+    match (entry.key(), entry.value()) {
+        (Key::Bare(name), BlockValue::Empty) => Some(name),
+        _ => None,
+    }
+}
+
+fn invalid(message: impl Into<String>, entry: &KeyValueEntry) -> Error {
+    Error::InvalidSchema(message.into(), entry.location())
+}
+
+impl Schema {
+    /// Reads a schema document. Every schema named with `schema` or `each`
+    /// must be defined in it.
+    pub fn from_block(document: &KeyValueBlock) -> Result<Self> {
+        let mut schema = Self::default();
+        for entry in &document.entries {
+            let Key::Name(name) = entry.key() else {
+                return Err(invalid("a schema must have a name", entry));
+            };
+            if schema.shapes.contains_key(name) {
+                return Err(invalid(format!("schema `{name}` is defined more than once"), entry));
+            }
+            let shape = Shape::from_entry(name, entry)?;
+            schema.order.push(name.clone());
+            schema.shapes.insert(name.clone(), shape);
+        }
+
+        for name in &schema.order {
+            schema.check_references(&schema.shapes[name])?;
+        }
+        Ok(schema)
+    }
+
+    fn check_references(&self, shape: &Shape) -> Result<()> {
+        for field in &shape.fields {
+            for reference in [&field.schema, &field.each].into_iter().flatten() {
+                match reference {
+                    ShapeRef::Named(name, location) if !self.shapes.contains_key(name) => {
+                        return Err(Error::InvalidSchema(format!("no schema is named `{name}`"), *location));
+                    }
+                    ShapeRef::Named(..) => {}
+                    ShapeRef::Inline(inner) => self.check_references(inner)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks every top-level entry of an expanded document against the
+    /// schemas whose `when` block it matches, in the order the schemas were
+    /// defined.
+    pub fn validate(&self, document: &KeyValueBlock) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        for entry in &document.entries {
+            let BlockValue::Block(block) = entry.value() else {
+                continue;
+            };
+            let path = entry.key().to_string();
+            for name in &self.order {
+                let shape = &self.shapes[name];
+                if !shape.when.is_empty() && shape.matches(block) {
+                    self.validate_block(shape, block, entry, &path, &mut diagnostics);
+                }
+            }
+        }
+        diagnostics
+    }
+
+    fn resolve<'a>(&'a self, reference: &'a ShapeRef) -> &'a Shape {
+        match reference {
+            ShapeRef::Named(name, _) => &self.shapes[name],
+            ShapeRef::Inline(shape) => shape,
+        }
+    }
+
+    /// Checks `block`, the value of `owner`, against `shape`.
+    fn validate_block(
+        &self,
+        shape: &Shape,
+        block: &KeyValueBlock,
+        owner: &KeyValueEntry,
+        path: &str,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        for field in &shape.fields {
+            let found = block.entries.iter().find(|e| matches!(e.key(), Key::Name(n) if *n == field.name));
+            match found {
+                Some(entry) => self.validate_field(field, entry, &format!("{path}.{}", field.name), diagnostics),
+                None if field.required => diagnostics.push(
+                    Diagnostic::new(DiagnosticCategory::MissingKey, owner.location(), String::new())
+                        .with_span(owner.span())
+                        .with_message(format!("`{path}` is missing the required key `{}`", field.name))
+                        .with_related(field.location, field.span, format!("required by schema `{}`", shape.name)),
+                ),
+                None => {}
+            }
+        }
+
+        if shape.closed {
+            for entry in &block.entries {
+                let known = matches!(entry.key(), Key::Name(n) if shape.fields.iter().any(|f| f.name == *n));
+                if !known {
+                    diagnostics.push(
+                        Diagnostic::new(DiagnosticCategory::UnknownKey, entry.location(), String::new())
+                            .with_span(entry.span())
+                            .with_message(format!("`{path}.{}` is not part of schema `{}`", entry.key(), shape.name))
+                            .with_related(shape.location, shape.span, "the schema is closed"),
+                    );
+                }
+            }
+        }
+    }
+
+    fn validate_field(&self, field: &Field, entry: &KeyValueEntry, path: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let value = entry.value();
+        let report = |category, message: String| {
+            Diagnostic::new(category, entry.location(), String::new())
+                .with_span(entry.span())
+                .with_message(message)
+                .with_related(field.location, field.span, "expected by this field")
+        };
+
+        if !field.kind.accepts(value) {
+            let message = format!("`{path}` should be {}, found {}", field.kind.describe(), value.describe());
+            diagnostics.push(report(DiagnosticCategory::WrongType, message));
+            return;
+        }
+
+        if !field.one_of.is_empty() && !field.one_of.contains(value) {
+            let allowed = field.one_of.iter().map(source).collect::<Vec<_>>();
+            let allowed = match allowed.as_slice() {
+                [one] => one.clone(),
+                [rest @ .., last] => format!("{} or {last}", rest.join(", ")),
+                [] => unreachable!(),
+            };
+            let message = format!("`{path}` must be {allowed}, found {}", source(value));
+            diagnostics.push(report(DiagnosticCategory::DisallowedValue, message));
+        }
+
+        if let Some(n) = number(value) {
+            let message = match (field.min, field.max) {
+                (Some(min), _) if n < min => Some(format!("`{path}` must be at least {min}, found {}", source(value))),
+                (_, Some(max)) if n > max => Some(format!("`{path}` must be at most {max}, found {}", source(value))),
+                _ => None,
+            };
+            if let Some(message) = message {
+                diagnostics.push(report(DiagnosticCategory::OutOfRange, message));
+            }
+        }
+
+        let BlockValue::Block(block) = value else {
+            if field.schema.is_some() || field.each.is_some() {
+                let message = format!("`{path}` should be a block, found {}", value.describe());
+                diagnostics.push(report(DiagnosticCategory::WrongType, message));
+            }
+            return;
+        };
+        if let Some(reference) = &field.schema {
+            self.validate_block(self.resolve(reference), block, entry, path, diagnostics);
+        }
+        if let Some(reference) = &field.each {
+            let shape = self.resolve(reference);
+            for inner in &block.entries {
+                let path = format!("{path}.{}", inner.key());
+                match inner.value() {
+                    BlockValue::Block(contents) => self.validate_block(shape, contents, inner, &path, diagnostics),
+                    value => diagnostics.push(
+                        Diagnostic::new(DiagnosticCategory::WrongType, inner.location(), String::new())
+                            .with_span(inner.span())
+                            .with_message(format!("`{path}` should be a block, found {}", value.describe()))
+                            .with_related(field.location, field.span, "expected by this field"),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+impl Shape {
+    fn from_entry(name: &str, entry: &KeyValueEntry) -> Result<Self> {
+        let BlockValue::Block(block) = entry.value() else {
+            return Err(invalid(format!("schema `{name}` must be a block"), entry));
+        };
+        let mut shape = Shape {
+            name: name.to_string(),
+            location: entry.location(),
+            span: entry.span(),
+            when: vec![],
+            closed: false,
+            fields: vec![],
+        };
+
+        for setting in &block.entries {
+            match (setting.key(), setting.value()) {
+                _ if flag(setting) == Some("closed") => shape.closed = true,
+                (Key::Name(key), BlockValue::Block(when)) if key == "when" => {
+                    for condition in &when.entries {
+                        let Key::Name(key) = condition.key() else {
+                            return Err(invalid("a `when` condition must have a name", condition));
+                        };
+                        shape.when.push((key.clone(), condition.value().clone()));
+                    }
+                }
+                (Key::Name(key), BlockValue::Block(fields)) if key == "fields" => {
+                    for field in &fields.entries {
+                        shape.fields.push(Field::from_entry(field)?);
+                    }
+                }
+                _ => {
+                    return Err(invalid(
+                        format!("expected `when`, `fields` or `closed` in schema `{name}`, found `{}`", setting.key()),
+                        setting,
+                    ))
+                }
+            }
+        }
+        Ok(shape)
+    }
+
+    fn matches(&self, block: &KeyValueBlock) -> bool {
+        self.when.iter().all(|(key, expected)| {
+            block
+                .entries
+                .iter()
+                .any(|e| matches!(e.key(), Key::Name(n) if n == key) && e.value() == expected)
+        })
+    }
+}
+
+impl Field {
+    fn from_entry(entry: &KeyValueEntry) -> Result<Self> {
+        let Key::Name(name) = entry.key() else {
+            return Err(invalid("a field must have a name", entry));
+        };
+        let BlockValue::Block(settings) = entry.value() else {
+            return Err(invalid(format!("field `{name}` must be a block"), entry));
+        };
+        let mut field = Field {
+            name: name.clone(),
+            location: entry.location(),
+            span: entry.span(),
+            kind: ValueKind::Any,
+            required: false,
+            one_of: vec![],
+            min: None,
+            max: None,
+            schema: None,
+            each: None,
+        };
+
+        for setting in &settings.entries {
+            if flag(setting) == Some("required") {
+                field.required = true;
+                continue;
+            }
+            let key = setting.key().to_string();
+            match (key.as_str(), setting.value()) {
+                ("type", BlockValue::Literal(kind)) => {
+                    field.kind = ValueKind::parse(kind).ok_or_else(|| {
+                        invalid(
+                            format!("unknown type `{kind}`, expected string, integer, float, number, block or any"),
+                            setting,
+                        )
+                    })?;
+                }
+                ("one_of", BlockValue::Block(values)) => {
+                    field.one_of = values
+                        .entries
+                        .iter()
+                        .map(|value| match flag(value) {
+                            Some(name) => BlockValue::Literal(name.to_string()),
+                            None => value.value().clone(),
+                        })
+                        .collect();
+                }
+                ("min", value) | ("max", value) => {
+                    let bound = number(value).ok_or_else(|| invalid(format!("`{key}` must be a number"), setting))?;
+                    match key.as_str() {
+                        "min" => field.min = Some(bound),
+                        _ => field.max = Some(bound),
+                    }
+                }
+                ("schema", value) | ("each", value) => {
+                    let reference = match value {
+                        BlockValue::Literal(name) => ShapeRef::Named(name.clone(), setting.location()),
+                        BlockValue::Block(_) => ShapeRef::Inline(Box::new(Shape::from_entry(name, setting)?)),
+                        _ => return Err(invalid(format!("`{key}` must name a schema or be one"), setting)),
+                    };
+                    match key.as_str() {
+                        "schema" => field.schema = Some(reference),
+                        _ => field.each = Some(reference),
+                    }
+                }
+                _ => return Err(invalid(format!("unexpected `{key}` in field `{name}`"), setting)),
+            }
+        }
+        Ok(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{diagnostic::DiagnosticCategory, testing::document, token::SourceLoc};
+
+    use super::Schema;
+
+    const SCHEMA: &str = "\
+Table = {
+  when = { type = \"Table\" },
+  fields = {
+    id = { type = \"integer\", min = 1, required },
+    type = { one_of = { Table, Table Occurrence } },
+    fields = { type = \"block\", each = \"Field\" }
+  }
+}
+Field = {
+  closed,
+  fields = { type = { one_of = { Text, Number } }, comment = { type = \"string\" } }
+}
+";
+
+    #[test]
+    fn validates_documents() {
+        let schema = Schema::from_block(&document(SCHEMA)).unwrap();
+        let text = "\
+Quotes = { type = \"Table\", id = 0, fields = { a = { type = \"Date\" }, b = { colour = \"red\" } } }
+Machines = { type = \"Table\", fields = 3 }
+Notes = { type = \"Layout\" }
+";
+        let diagnostics = schema.validate(&document(text));
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.category(), d.location().line(), d.message()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (DiagnosticCategory::OutOfRange, 1, "`Quotes.id` must be at least 1, found 0"),
+                (DiagnosticCategory::DisallowedValue, 1, "`Quotes.fields.a.type` must be \"Text\" or \"Number\", found \"Date\""),
+                (DiagnosticCategory::UnknownKey, 1, "`Quotes.fields.b.colour` is not part of schema `Field`"),
+                (DiagnosticCategory::MissingKey, 2, "`Machines` is missing the required key `id`"),
+                (DiagnosticCategory::WrongType, 2, "`Machines.fields` should be a block, found an integer"),
+            ]
+        );
+        assert_eq!(diagnostics[3].related()[0].location, SourceLoc::new(4, 5));
+    }
+
+    #[test]
+    fn rejects_invalid_schemas() {
+        let error = Schema::from_block(&document("A = { fields = { x = { schema = \"B\" } } }")).unwrap_err();
+        assert_eq!(error.to_string(), "1:24: no schema is named `B`");
+        let error = Schema::from_block(&document("A = { fields = { x = { type = \"text\" } } }")).unwrap_err();
+        assert_eq!(error.location(), Some(SourceLoc::new(1, 24)));
+        assert!(Schema::from_block(&document("A = { colour = 1 }")).is_err());
+        let error = Schema::from_block(&document("A = { fields = { x = { $required } } }")).unwrap_err();
+        assert_eq!(error.to_string(), "1:24: unexpected `$required` in field `x`");
+    }
+}
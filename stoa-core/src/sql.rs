@@ -0,0 +1,705 @@
+//! `CREATE TABLE` statements for the tables a document describes. Every
+//! top-level entry with `type = "Table"` is a table, and each entry of its
+//! `fields` block is a column:
+//!
+//! ```text
+//! Quotes = {
+//!   type = "Table",
+//!   fields = {
+//!     id = { type = "Integer", primary key },
+//!     title = { type = "Text", required, max length = 80 },
+//!     machine = { type = "Integer", references = &Machines.fields.id },
+//!     total = { type = "Number", default = 0 }
+//!   }
+//! }
+//! ```
+//!
+//! A field has a `type`, one of the [`FieldType`]s, and may be marked
+//! `required`, `unique` or `primary key`. `default` gives a value for new
+//! rows, `max length` limits text, and `references` names another table, or
+//! one of its fields, as a foreign key. `column` gives the column's name when
+//! it is not the field's, such as a name stoa cannot spell.
+//!
+//! [`import_tables`] goes the other way, describing the tables of an existing
+//! database in these terms.
+
+use std::{collections::HashSet, fmt::Write};
+
+use crate::{
+    error::{Error, Result},
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry},
+    token::{match_keyword, SourceLoc},
+    validate::{self, References, OCCURRENCE_TYPE, TABLE_TYPE},
+};
+
+/// The key holding a table's fields.
+pub const FIELDS: &str = "fields";
+/// The key giving a field's column name, when it is not the field's.
+pub const COLUMN: &str = "column";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Sqlite,
+    Postgres,
+}
+
+/// The types a field may have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Text,
+    Number,
+    Integer,
+    Boolean,
+    Date,
+    Time,
+    Timestamp,
+    /// Binary data, such as a file.
+    Container,
+}
+
+impl FieldType {
+    pub const ALL: [Self; 8] = [
+        Self::Text,
+        Self::Number,
+        Self::Integer,
+        Self::Boolean,
+        Self::Date,
+        Self::Time,
+        Self::Timestamp,
+        Self::Container,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Text => "Text",
+            Self::Number => "Number",
+            Self::Integer => "Integer",
+            Self::Boolean => "Boolean",
+            Self::Date => "Date",
+            Self::Time => "Time",
+            Self::Timestamp => "Timestamp",
+            Self::Container => "Container",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    /// The type of a column declared as `declared`, following SQLite's
+    /// affinity rules, along with the length of a `VARCHAR(n)`.
+    pub fn from_declared(declared: &str) -> (Self, Option<i64>) {
+        let upper = declared.to_uppercase();
+        let kind = if upper.contains("BOOL") {
+            Self::Boolean
+        } else if upper.contains("INT") {
+            Self::Integer
+        } else if upper.contains("TIMESTAMP") || upper.contains("DATETIME") {
+            Self::Timestamp
+        } else if upper.contains("DATE") {
+            Self::Date
+        } else if upper.contains("TIME") {
+            Self::Time
+        } else if upper.contains("CHAR") || upper.contains("CLOB") || upper.contains("TEXT") {
+            Self::Text
+        } else if upper.contains("BLOB") || upper.trim().is_empty() {
+            Self::Container
+        } else {
+            Self::Number
+        };
+        let length = match kind {
+            Self::Text => upper
+                .split_once('(')
+                .and_then(|(_, rest)| rest.split_once(')'))
+                .and_then(|(n, _)| n.trim().parse().ok()),
+            _ => None,
+        };
+        (kind, length)
+    }
+
+    /// The column type in `dialect`. SQLite stores dates and times as text.
+    fn column(self, dialect: Dialect, max_length: Option<i64>) -> String {
+        match (self, dialect) {
+            (Self::Text, Dialect::Postgres) => match max_length {
+                Some(n) => format!("VARCHAR({n})"),
+                None => "TEXT".to_string(),
+            },
+            (Self::Text | Self::Date | Self::Time | Self::Timestamp, Dialect::Sqlite) => "TEXT".to_string(),
+            (Self::Number, Dialect::Sqlite) => "REAL".to_string(),
+            (Self::Number, Dialect::Postgres) => "NUMERIC".to_string(),
+            (Self::Integer | Self::Boolean, Dialect::Sqlite) => "INTEGER".to_string(),
+            (Self::Integer, Dialect::Postgres) => "BIGINT".to_string(),
+            (Self::Boolean, Dialect::Postgres) => "BOOLEAN".to_string(),
+            (Self::Date, Dialect::Postgres) => "DATE".to_string(),
+            (Self::Time, Dialect::Postgres) => "TIME".to_string(),
+            (Self::Timestamp, Dialect::Postgres) => "TIMESTAMP".to_string(),
+            (Self::Container, Dialect::Sqlite) => "BLOB".to_string(),
+            (Self::Container, Dialect::Postgres) => "BYTEA".to_string(),
+        }
+    }
+}
+
+/// The tables of a resolved document, in document order, except that a
+/// table is created after the tables its foreign keys refer to.
+pub fn create_tables(document: &KeyValueBlock, dialect: Dialect) -> Result<String> {
+    let index = References::new(document);
+    let tables = document
+        .entries
+        .iter()
+        .filter(|entry| validate::entry_type(entry) == Some(TABLE_TYPE))
+        .collect::<Vec<_>>();
+
+    let statements = tables
+        .iter()
+        .map(|table| create_table(table, &index, dialect))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut order = vec![];
+    for i in 0..statements.len() {
+        visit(i, &statements, &mut order, &mut vec![]);
+    }
+    Ok(order
+        .into_iter()
+        .map(|i| statements[i].sql.as_str())
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+struct Statement {
+    table: String,
+    sql: String,
+    /// The tables its foreign keys refer to.
+    dependencies: Vec<String>,
+}
+
+/// Places the tables `table` depends on before it. A table that is already
+/// being visited is part of a cycle, which is left in document order.
+fn visit(table: usize, statements: &[Statement], order: &mut Vec<usize>, visiting: &mut Vec<usize>) {
+    if order.contains(&table) || visiting.contains(&table) {
+        return;
+    }
+    visiting.push(table);
+    for dependency in &statements[table].dependencies {
+        if let Some(i) = statements.iter().position(|s| s.table == *dependency) {
+            visit(i, statements, order, visiting);
+        }
+    }
+    visiting.pop();
+    order.push(table);
+}
+
+fn create_table(table: &KeyValueEntry, index: &References, dialect: Dialect) -> Result<Statement> {
+    let mut columns = vec![];
+    let mut dependencies = vec![];
+    if let Some(fields) = validate::value(table, FIELDS) {
+        let BlockValue::Block(fields) = fields.value() else {
+            return Err(Error::CannotExport(format!("the fields of `{}` must be a block", table.key()), fields.location()));
+        };
+        for field in &fields.entries {
+            let (column, dependency) = column(table, field, index, dialect)?;
+            columns.push(column);
+            dependencies.extend(dependency);
+        }
+    }
+
+    let name = table.key().to_string();
+    let mut sql = format!("CREATE TABLE {} (", identifier(&name));
+    for (i, column) in columns.iter().enumerate() {
+        let separator = if i + 1 < columns.len() { "," } else { "" };
+        let _ = write!(sql, "\n  {column}{separator}");
+    }
+    sql.push_str(if columns.is_empty() { ");\n" } else { "\n);\n" });
+    Ok(Statement { table: name, sql, dependencies })
+}
+
+fn column(table: &KeyValueEntry, field: &KeyValueEntry, index: &References, dialect: Dialect) -> Result<(String, Option<String>)> {
+    let path = format!("{}.{FIELDS}.{}", table.key(), field.key());
+    let invalid = |message: String, entry: &KeyValueEntry| Error::CannotExport(message, entry.location());
+    let BlockValue::Block(settings) = field.value() else {
+        return Err(invalid(format!("`{path}` must be a block"), field));
+    };
+
+    let mut field_type = None;
+    let mut max_length = None;
+    let mut constraints = vec![];
+    let mut dependency = None;
+    for setting in &settings.entries {
+        let flag = match (setting.key(), setting.value()) {
+            (Key::Bare(flag) | Key::MacroValue(flag), BlockValue::Empty) => Some(flag.as_str()),
+            _ => None,
+        };
+        match (flag, setting.key().to_string().as_str(), setting.value()) {
+            (Some("primary key"), ..) => constraints.push("PRIMARY KEY".to_string()),
+            (Some("required"), ..) => constraints.push("NOT NULL".to_string()),
+            (Some("unique"), ..) => constraints.push("UNIQUE".to_string()),
+            (None, "type", BlockValue::Literal(kind)) => {
+                let kind = FieldType::from_name(kind).ok_or_else(|| {
+                    let names = FieldType::ALL.map(FieldType::name).join(", ");
+                    invalid(format!("unknown field type `{kind}` in `{path}`, expected one of {names}"), setting)
+                })?;
+                field_type = Some(kind);
+            }
+            (None, "max length", BlockValue::Integer(n)) => max_length = Some(*n),
+            (None, COLUMN, BlockValue::Literal(_)) => {}
+            (None, "default", value) => {
+                let value = literal(value).ok_or_else(|| invalid(format!("the default of `{path}` must be a string or a number"), setting))?;
+                constraints.push(format!("DEFAULT {value}"));
+            }
+            (None, "references", value) => {
+                let (target, column) = foreign_key(value, index)
+                    .ok_or_else(|| invalid(format!("`{path}` must reference a table or one of its fields"), setting))?;
+                let column = column.map(|c| format!(" ({})", identifier(&c))).unwrap_or_default();
+                constraints.push(format!("REFERENCES {}{column}", identifier(&target)));
+                dependency = Some(target);
+            }
+            (_, key, _) => return Err(invalid(format!("unexpected `{key}` in `{path}`"), setting)),
+        }
+    }
+
+    let field_type = field_type.ok_or_else(|| invalid(format!("`{path}` has no `type`"), field))?;
+    let mut column = format!("{} {}", identifier(&column_name(field)), field_type.column(dialect, max_length));
+    for constraint in constraints {
+        column.push(' ');
+        column.push_str(&constraint);
+    }
+    Ok((column, dependency))
+}
+
+/// The name of a field's column: its `column`, if it has one, or its key.
+fn column_name(field: &KeyValueEntry) -> String {
+    match validate::value(field, COLUMN).map(KeyValueEntry::value) {
+        Some(BlockValue::Literal(name)) => name.clone(),
+        _ => field.key().to_string(),
+    }
+}
+
+/// The table, and possibly the column, a `references` value names: either a
+/// table, or a field in its `fields` block.
+fn foreign_key(value: &BlockValue, index: &References) -> Option<(String, Option<String>)> {
+    let table = index.resolve(value)?;
+    let (name, column) = match value {
+        BlockValue::Reference(path) => match path.as_slice() {
+            [table] => (table.clone(), None),
+            [name, fields, _] if fields == FIELDS => (name.clone(), Some(column_name(table))),
+            _ => return None,
+        },
+        _ => (table.key().to_string(), None),
+    };
+    let table = index.resolve(&BlockValue::Literal(name.clone()))?;
+    (validate::entry_type(table) == Some(TABLE_TYPE)).then_some((name, column))
+}
+
+fn literal(value: &BlockValue) -> Option<String> {
+    match value {
+        BlockValue::Literal(s) => Some(format!("'{}'", s.replace('\'', "''"))),
+        BlockValue::Integer(n) => Some(n.to_string()),
+        BlockValue::Float(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Quotes a table or column name, which may contain spaces.
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// A table read from an existing database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableInfo {
+    pub name: String,
+    pub columns: Vec<ColumnInfo>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnInfo {
+    pub name: String,
+    /// The type as written in `CREATE TABLE`, such as `VARCHAR(80)`.
+    pub declared_type: String,
+    /// Only set when the column is the whole primary key.
+    pub primary_key: bool,
+    pub not_null: bool,
+    pub unique: bool,
+    /// The default as written in `CREATE TABLE`, such as `'draft'` or `0`.
+    pub default: Option<String>,
+}
+
+/// A foreign key of a single column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+    pub column: String,
+    pub table: String,
+    /// The column referred to, or `None` for the table's primary key.
+    pub to: Option<String>,
+}
+
+/// Describes existing tables as a stoa document: an entry of type `Table`
+/// for each, followed by a `Table Occurrence` for each foreign key, named
+/// after the two tables it joins. Both kinds are numbered from 1.
+///
+/// Names are kept where stoa can spell them, with other characters turned
+/// into spaces and keywords such as `macro` followed by `_`. Names that then
+/// read the same, such as `quote-lines` and `quote lines`, are told apart by
+/// a number, as occurrences are. A field whose key is not its column's name
+/// keeps that name in `column`, so export gives the column back. Defaults
+/// other than plain strings and numbers, such as `CURRENT_TIMESTAMP`, are
+/// left out.
+pub fn import_tables(tables: &[TableInfo]) -> KeyValueBlock {
+    let entry = |key: &str, value: BlockValue| KeyValueEntry::new(Key::Name(key.to_string()), SourceLoc::new(0, 0), value);
+    let flag = |name: &str| KeyValueEntry::new(Key::Bare(name.to_string()), SourceLoc::new(0, 0), BlockValue::Empty);
+    let block = |entries: Vec<KeyValueEntry>| BlockValue::Block(KeyValueBlock { entries, ..KeyValueBlock::new() });
+
+    let mut top_level = UniqueNames::default();
+    let names = tables
+        .iter()
+        .map(|table| {
+            let mut fields = UniqueNames::default();
+            let columns = table.columns.iter().map(|column| fields.add(name(&column.name))).collect::<Vec<_>>();
+            (top_level.add(name(&table.name)), columns)
+        })
+        .collect::<Vec<_>>();
+    let table_index = |table: &str| tables.iter().position(|t| t.name == table);
+    let table_name = |table: &str| table_index(table).map_or_else(|| name(table), |i| names[i].0.clone());
+    let column_name = |table: &str, column: &str| {
+        let found = table_index(table)
+            .and_then(|i| tables[i].columns.iter().position(|c| c.name == column).map(|j| &names[i].1[j]));
+        found.cloned().unwrap_or_else(|| name(column))
+    };
+
+    let mut document = KeyValueBlock::new();
+    for (id, (table, (table_key, field_keys))) in (1..).zip(tables.iter().zip(&names)) {
+        let mut fields = vec![];
+        for (column, field_key) in table.columns.iter().zip(field_keys) {
+            let (kind, max_length) = FieldType::from_declared(&column.declared_type);
+            let mut settings = vec![entry(validate::TYPE, BlockValue::Literal(kind.name().to_string()))];
+            // Stoa strings cannot hold a double quote.
+            if *field_key != column.name && !column.name.contains('"') {
+                settings.push(entry(COLUMN, BlockValue::Literal(column.name.clone())));
+            }
+            if column.primary_key {
+                settings.push(flag("primary key"));
+            }
+            if column.not_null && !column.primary_key {
+                settings.push(flag("required"));
+            }
+            if column.unique {
+                settings.push(flag("unique"));
+            }
+            if let Some(n) = max_length {
+                settings.push(entry("max length", BlockValue::Integer(n)));
+            }
+            if let Some(value) = column.default.as_deref().and_then(default_value) {
+                settings.push(entry("default", value));
+            }
+            if let Some(key) = table.foreign_keys.iter().find(|k| k.column == column.name) {
+                let mut path = vec![table_name(&key.table)];
+                if let Some(to) = &key.to {
+                    path.extend([FIELDS.to_string(), column_name(&key.table, to)]);
+                }
+                settings.push(entry("references", BlockValue::Reference(path)));
+            }
+            fields.push(entry(field_key, block(settings)));
+        }
+        document.add(entry(
+            table_key,
+            block(vec![
+                entry(validate::ID, BlockValue::Integer(id)),
+                entry(validate::TYPE, BlockValue::Literal(TABLE_TYPE.to_string())),
+                entry(FIELDS, block(fields)),
+            ]),
+        ));
+    }
+
+    let keys = tables.iter().zip(&names).flat_map(|(table, (key, _))| table.foreign_keys.iter().map(move |k| (key, k)));
+    for (id, (table_key, key)) in (1..).zip(keys) {
+        let occurrence = top_level.add(format!("{table_key} {}", table_name(&key.table)));
+        document.add(entry(
+            &occurrence,
+            block(vec![
+                entry(validate::ID, BlockValue::Integer(id)),
+                entry(validate::TYPE, BlockValue::Literal(OCCURRENCE_TYPE.to_string())),
+                entry(validate::TABLE, BlockValue::Literal(table_name(&key.table))),
+            ]),
+        ));
+    }
+    document
+}
+
+/// Keys already given to the entries of a block.
+#[derive(Default)]
+struct UniqueNames(HashSet<String>);
+
+impl UniqueNames {
+    /// `name`, or the first of `name 2`, `name 3` and so on that is not
+    /// taken yet, which is then taken.
+    fn add(&mut self, name: String) -> String {
+        let unique = match self.0.contains(&name) {
+            false => name,
+            true => (2..).map(|n| format!("{name} {n}")).find(|n| !self.0.contains(n)).expect("names run out"),
+        };
+        self.0.insert(unique.clone());
+        unique
+    }
+}
+
+/// A name stoa can spell: words of identifier characters separated by
+/// single spaces.
+fn name(text: &str) -> String {
+    let words = text
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .map(|word| match match_keyword(word) {
+            Some(_) => format!("{word}_"),
+            None => word.to_string(),
+        })
+        .collect::<Vec<_>>();
+    match words.first() {
+        None => "_".to_string(),
+        Some(first) if first.starts_with(|c: char| c.is_numeric()) => format!("_{}", words.join(" ")),
+        Some(_) => words.join(" "),
+    }
+}
+
+fn default_value(default: &str) -> Option<BlockValue> {
+    if let Some(text) = default.strip_prefix('\'').and_then(|d| d.strip_suffix('\'')) {
+        // Stoa strings cannot hold a double quote.
+        return (!text.contains('"')).then(|| BlockValue::Literal(text.replace("''", "'")));
+    }
+    // Nor can it spell negative numbers.
+    match default.parse::<i64>() {
+        Ok(n) => (n >= 0).then_some(BlockValue::Integer(n)),
+        Err(_) => default.parse().ok().filter(|n: &f64| n.is_finite() && *n >= 0.0).map(BlockValue::Float),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        keyvalue::{BlockValue, KeyValueBlock},
+        printer::to_source,
+        query,
+        testing::document,
+        token::SourceLoc,
+    };
+
+    use super::{create_tables, import_tables, ColumnInfo, Dialect, FieldType, ForeignKey, TableInfo};
+
+    const SOURCE: &str = "\
+Quotes = {
+  type = \"Table\",
+  fields = {
+    id = { type = \"Integer\", primary key },
+    title = { type = \"Text\", required, max length = 80 },
+    machine = { type = \"Integer\", references = &Machines.fields.id },
+    total = { type = \"Number\", default = 0 }
+  }
+}
+Machines = { type = \"Table\", fields = { id = { type = \"Integer\", primary key }, name = { type = \"Text\", unique } } }
+Quotes Machines = { type = \"Table Occurrence\", table = \"Machines\" }
+";
+
+    #[test]
+    fn create_tables_in_dependency_order() {
+        assert_eq!(
+            create_tables(&document(SOURCE), Dialect::Sqlite).unwrap(),
+            "\
+CREATE TABLE \"Machines\" (
+  \"id\" INTEGER PRIMARY KEY,
+  \"name\" TEXT UNIQUE
+);
+
+CREATE TABLE \"Quotes\" (
+  \"id\" INTEGER PRIMARY KEY,
+  \"title\" TEXT NOT NULL,
+  \"machine\" INTEGER REFERENCES \"Machines\" (\"id\"),
+  \"total\" REAL DEFAULT 0
+);
+"
+        );
+        let postgres = create_tables(&document(SOURCE), Dialect::Postgres).unwrap();
+        assert!(postgres.contains("\"title\" VARCHAR(80) NOT NULL,"));
+        assert!(postgres.contains("\"total\" NUMERIC DEFAULT 0"));
+    }
+
+    #[test]
+    fn reports_bad_fields() {
+        let error = create_tables(&document("A = { type = \"Table\", fields = { x = { type = \"Money\" } } }"), Dialect::Sqlite)
+            .unwrap_err();
+        assert_eq!(error.location(), Some(SourceLoc::new(1, 40)));
+        assert!(error.message().starts_with("unknown field type `Money` in `A.fields.x`"));
+
+        let error = create_tables(&document("A = { type = \"Table\", fields = { x = { type = \"Integer\", references = &B } } }"), Dialect::Sqlite)
+            .unwrap_err();
+        assert_eq!(error.message(), "`A.fields.x` must reference a table or one of its fields");
+    }
+
+    fn column(name: &str, declared_type: &str) -> ColumnInfo {
+        ColumnInfo {
+            name: name.to_string(),
+            declared_type: declared_type.to_string(),
+            primary_key: false,
+            not_null: false,
+            unique: false,
+            default: None,
+        }
+    }
+
+    #[test]
+    fn imports_tables() {
+        assert_eq!(FieldType::from_declared("varchar(80)"), (FieldType::Text, Some(80)));
+        assert_eq!(FieldType::from_declared("DATETIME"), (FieldType::Timestamp, None));
+        assert_eq!(FieldType::from_declared("DECIMAL(10,2)"), (FieldType::Number, None));
+        assert_eq!(FieldType::from_declared(""), (FieldType::Container, None));
+
+        let machines = TableInfo {
+            name: "machines".to_string(),
+            columns: vec![ColumnInfo { primary_key: true, ..column("id", "INTEGER") }],
+            foreign_keys: vec![],
+        };
+        let quotes = TableInfo {
+            name: "quote-lines".to_string(),
+            columns: vec![
+                ColumnInfo { not_null: true, default: Some("'it''s'".to_string()), ..column("title", "VARCHAR(80)") },
+                ColumnInfo { unique: true, default: Some("CURRENT_TIMESTAMP".to_string()), ..column("made", "DATETIME") },
+                column("machine", "INTEGER"),
+                column("backup", "INTEGER"),
+            ],
+            foreign_keys: vec![
+                ForeignKey { column: "machine".to_string(), table: "machines".to_string(), to: Some("id".to_string()) },
+                ForeignKey { column: "backup".to_string(), table: "machines".to_string(), to: None },
+            ],
+        };
+        let imported = import_tables(&[machines, quotes]);
+        let source = to_source(&imported);
+        assert_eq!(
+            source,
+            "\
+machines = {
+  id = 1,
+  type = \"Table\",
+  fields = {
+    id = {
+      type = \"Integer\",
+      primary key
+    }
+  }
+}
+
+quote lines = {
+  id = 2,
+  type = \"Table\",
+  fields = {
+    title = {
+      type = \"Text\",
+      required,
+      max length = 80,
+      default = \"it's\"
+    },
+    made = {
+      type = \"Timestamp\",
+      unique
+    },
+    machine = {
+      type = \"Integer\",
+      references = &machines.fields.id
+    },
+    backup = {
+      type = \"Integer\",
+      references = &machines
+    }
+  }
+}
+
+quote lines machines = {
+  id = 1,
+  type = \"Table Occurrence\",
+  table = \"machines\"
+}
+
+quote lines machines 2 = {
+  id = 2,
+  type = \"Table Occurrence\",
+  table = \"machines\"
+}
+"
+        );
+        assert!(create_tables(&document(&source), Dialect::Sqlite).unwrap().starts_with("CREATE TABLE \"machines\""));
+    }
+
+    #[test]
+    fn numbers_names_that_read_the_same() {
+        let lines = TableInfo {
+            name: "quote-lines".to_string(),
+            columns: vec![column("a-b", "TEXT"), column("a b", "TEXT")],
+            foreign_keys: vec![],
+        };
+        let other = TableInfo {
+            name: "quote lines".to_string(),
+            columns: vec![column("line", "INTEGER")],
+            foreign_keys: vec![ForeignKey {
+                column: "line".to_string(),
+                table: "quote lines".to_string(),
+                to: Some("line".to_string()),
+            }],
+        };
+        let parent = TableInfo {
+            name: "quote lines quote lines 2".to_string(),
+            columns: vec![column("line", "INTEGER")],
+            foreign_keys: vec![ForeignKey {
+                column: "line".to_string(),
+                table: "quote-lines".to_string(),
+                to: Some("a b".to_string()),
+            }],
+        };
+        let imported = import_tables(&[lines, other, parent]);
+        let keys = |block: &KeyValueBlock| block.entries.iter().map(|e| e.key().to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            keys(&imported),
+            [
+                "quote lines",
+                "quote lines 2",
+                "quote lines quote lines 2",
+                "quote lines 2 quote lines 2",
+                "quote lines quote lines 2 quote lines",
+            ]
+        );
+        let path = ["quote lines".to_string(), "fields".to_string()];
+        let Some(BlockValue::Block(fields)) = query::get(&imported, &path) else { panic!("no fields") };
+        assert_eq!(keys(fields), ["a b", "a b 2"]);
+        assert_eq!(
+            to_source(&imported).lines().filter(|line| line.contains("references")).collect::<Vec<_>>(),
+            ["      references = &quote lines 2.fields.line", "      references = &quote lines.fields.a b 2"]
+        );
+        assert!(create_tables(&document(&to_source(&imported)), Dialect::Sqlite).is_ok());
+    }
+
+    #[test]
+    fn keeps_column_names() {
+        let notes = TableInfo {
+            name: "notes".to_string(),
+            columns: vec![ColumnInfo { primary_key: true, ..column("macro", "INTEGER") }, column("note-text", "TEXT")],
+            foreign_keys: vec![],
+        };
+        let links = TableInfo {
+            name: "links".to_string(),
+            columns: vec![column("note", "INTEGER")],
+            foreign_keys: vec![ForeignKey { column: "note".to_string(), table: "notes".to_string(), to: Some("macro".to_string()) }],
+        };
+        let source = to_source(&import_tables(&[notes, links]));
+        assert!(source.contains("    macro_ = {\n      type = \"Integer\",\n      column = \"macro\",\n      primary key\n    },"));
+        assert!(source.contains("    note text = {\n      type = \"Text\",\n      column = \"note-text\"\n    }"));
+        assert_eq!(
+            create_tables(&document(&source), Dialect::Sqlite).unwrap(),
+            "\
+CREATE TABLE \"notes\" (
+  \"macro\" INTEGER PRIMARY KEY,
+  \"note-text\" TEXT
+);
+
+CREATE TABLE \"links\" (
+  \"note\" INTEGER REFERENCES \"notes\" (\"macro\")
+);
+"
+        );
+    }
+}
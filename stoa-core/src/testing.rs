@@ -0,0 +1,17 @@
+//! Helpers shared by the unit tests.
+
+use crate::{keyvalue::KeyValueBlock, lexer::lex_file, parser::parse, token::FileId};
+
+/// Parses a document that is expected to be free of syntax errors.
+pub(crate) fn document(text: &str) -> KeyValueBlock {
+    document_in(text, 0)
+}
+
+/// Like [`document`], with its locations in file `file`.
+pub(crate) fn document_in(text: &str, file: FileId) -> KeyValueBlock {
+    let mut diagnostics = vec![];
+    let tokens = lex_file(text, file, &mut diagnostics).unwrap();
+    let document = parse(&tokens, &mut diagnostics).unwrap();
+    assert!(diagnostics.is_empty());
+    document
+}
@@ -1,21 +1,71 @@
-use std::path::PathBuf;
+/// Identifies one input among several read together, such as the files
+/// given to the command line. A lone document is file 0.
+pub type FileId = u32;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct SourceLoc {
+    // Declared first so that locations sort by file, then position.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    file: FileId,
     line: u32,
     column: u32,
 }
 
 impl SourceLoc {
     pub fn new(line: u32, column: u32) -> Self {
-        Self { line, column }
+        Self { file: 0, line, column }
+    }
+
+    pub fn with_file(self, file: FileId) -> Self {
+        Self { file, ..self }
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+}
+
+impl std::fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A half open range of byte offsets into the source text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Token {
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub token_val: TokenValue,
+    #[cfg_attr(feature = "serde", serde(rename = "location"))]
     pub source_loc: SourceLoc,
+    pub span: Span,
 }
 
 impl Token {
@@ -23,11 +73,13 @@ impl Token {
         Self {
             token_val,
             source_loc,
+            span: Span::default(),
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum TokenKind {
     Assignment,
     Append,
@@ -47,14 +99,49 @@ pub enum TokenKind {
     Comma,
     Colon,
     Exclamation,
-    EOF,
+    Comment,
+    Eof,
 
     Macro,
     MacroParameter,
     MacroCall,
+    Reference,
+}
+
+/// Describes the kind of token for error messages, such as `expected a name`.
+impl std::fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Assignment => "`=`",
+            Self::Append => "`+=`",
+            Self::Identifier => "a name",
+            Self::IntegerLiteral => "an integer",
+            Self::FloatLiteral => "a float",
+            Self::String => "a string",
+            Self::True => "`true`",
+            Self::False => "`false`",
+            Self::OpenBrace => "`{`",
+            Self::CloseBrace => "`}`",
+            Self::OpenParen => "`(`",
+            Self::CloseParen => "`)`",
+            Self::OpenSquare => "`[`",
+            Self::CloseSquare => "`]`",
+            Self::Comma => "`,`",
+            Self::Colon => "`:`",
+            Self::Exclamation => "`!`",
+            Self::Comment => "a comment",
+            Self::Eof => "end of input",
+            Self::Macro => "`macro`",
+            Self::MacroParameter => "a macro parameter",
+            Self::MacroCall => "a macro call",
+            Self::Reference => "a reference",
+        })
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value"))]
 pub enum TokenValue {
     Assignment,
     Append,
@@ -75,11 +162,45 @@ pub enum TokenValue {
     Comma,
     Colon,
     Exclamation,
-    EOF,
+    Comment(String),
+    Eof,
 
     Macro,
     MacroParameter(String),
     MacroCall(String),
+    /// `&Quotes.fields.id`, as the path of keys it names.
+    Reference(Vec<String>),
+}
+
+/// Formats the token as it would be written in stoa source.
+impl std::fmt::Display for TokenValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Assignment => write!(f, "="),
+            Self::Append => write!(f, "+="),
+            Self::Identifier(s) => write!(f, "{}", s),
+            Self::IntegerLiteral(n) => write!(f, "{}", n),
+            Self::FloatLiteral(n) => write!(f, "{:?}", n),
+            Self::String(s) => write!(f, "\"{}\"", s),
+            Self::True => write!(f, "true"),
+            Self::False => write!(f, "false"),
+            Self::OpenBrace => write!(f, "{{"),
+            Self::CloseBrace => write!(f, "}}"),
+            Self::OpenParen => write!(f, "("),
+            Self::CloseParen => write!(f, ")"),
+            Self::OpenSquare => write!(f, "["),
+            Self::CloseSquare => write!(f, "]"),
+            Self::Comma => write!(f, ","),
+            Self::Colon => write!(f, ":"),
+            Self::Exclamation => write!(f, "!"),
+            Self::Comment(s) => write!(f, "#{}", s),
+            Self::Eof => Ok(()),
+            Self::Macro => write!(f, "macro"),
+            Self::MacroParameter(p) => write!(f, "${}", p),
+            Self::MacroCall(c) => write!(f, "@{}", c),
+            Self::Reference(path) => write!(f, "&{}", path.join(".")),
+        }
+    }
 }
 
 impl TokenValue {
@@ -102,10 +223,12 @@ impl TokenValue {
             Self::Comma => TokenKind::Comma,
             Self::Colon => TokenKind::Colon,
             Self::Exclamation => TokenKind::Exclamation,
-            Self::EOF => TokenKind::EOF,
+            Self::Comment(_) => TokenKind::Comment,
+            Self::Eof => TokenKind::Eof,
             Self::Macro => TokenKind::Macro,
             Self::MacroParameter(_) => TokenKind::MacroParameter,
             Self::MacroCall(_) => TokenKind::MacroCall,
+            Self::Reference(_) => TokenKind::Reference,
         }
     }
 
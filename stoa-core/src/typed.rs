@@ -0,0 +1,357 @@
+//! Conversion from documents into Rust types, along with the schema each type
+//! expects. `#[derive(Stoa)]`, from the `stoa-derive` crate, implements
+//! [`Stoa`] for structs with named fields:
+//!
+//! ```text
+//! #[derive(Stoa)]
+//! #[stoa(when(type = "Table"))]
+//! struct Table {
+//!     id: u32,
+//!     #[stoa(rename = "table name")]
+//!     name: Option<String>,
+//!     fields: BTreeMap<String, Field>,
+//! }
+//! ```
+//!
+//! A field that holds another such type refers to its schema by name, and
+//! the schema document of `Table` defines `Field` after it, so types may
+//! hold themselves.
+//!
+//! Errors point at the value that could not be converted, or at the block
+//! that is missing a key.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    diagnostic::Diagnostic,
+    error::{Error, Result},
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry},
+    lexer::lex,
+    parser::parse,
+    printer,
+    schema::Schema,
+    token::SourceLoc,
+};
+
+/// A type that can be read from a single value.
+pub trait FromStoa: Sized {
+    /// The settings of a schema field that holds this type, such as
+    /// `type = "integer"`.
+    fn field_schema() -> String;
+
+    /// The name of the schema for blocks of this type, if it is read from a
+    /// block with known keys.
+    fn schema_name() -> Option<&'static str> {
+        None
+    }
+
+    /// Adds the schemas this type refers to, by name and body, to those
+    /// found so far.
+    fn schemas(_schemas: &mut Vec<(&'static str, String)>) {}
+
+    /// Converts the value at `path`, which was found at `location`.
+    fn from_stoa(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Self>;
+
+    /// The value of a missing key, or `None` if the key is required.
+    fn missing() -> Option<Self> {
+        None
+    }
+}
+
+/// A type read from a block, which has a schema of its own.
+pub trait Stoa: FromStoa {
+    /// The name of the schema.
+    const NAME: &'static str;
+
+    /// The schema without its name: `when = { ... }, fields = { ... }`.
+    fn schema_body() -> String;
+
+    /// Converts `block`, the value at `path`, which was found at `location`.
+    fn from_block(block: &KeyValueBlock, path: &str, location: SourceLoc) -> Result<Self>;
+
+    /// Converts the value of a document entry.
+    fn from_entry(entry: &KeyValueEntry) -> Result<Self> {
+        Self::from_stoa(entry.value(), &entry.key().to_string(), entry.location())
+    }
+
+    /// The schema as a stoa document, in the canonical layout, followed by
+    /// the schemas of the types it holds.
+    fn schema_source() -> String {
+        printer::to_source(&schema_document::<Self>())
+    }
+
+    fn schema() -> Schema {
+        Schema::from_block(&schema_document::<Self>()).expect("generated schemas are valid")
+    }
+}
+
+/// Adds the schema of `T` unless it was already found, and returns whether
+/// it did, so that a type which holds itself is only described once.
+pub fn add_schema<T: Stoa>(schemas: &mut Vec<(&'static str, String)>) -> bool {
+    if schemas.iter().any(|(name, _)| *name == T::NAME) {
+        return false;
+    }
+    schemas.push((T::NAME, T::schema_body()));
+    true
+}
+
+fn schema_document<T: Stoa>() -> KeyValueBlock {
+    let mut schemas = vec![];
+    T::schemas(&mut schemas);
+    let text = schemas.iter().map(|(name, body)| format!("{name} = {{ {body} }}\n")).collect::<String>();
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+    let document = lex(&text, &mut diagnostics).and_then(|tokens| parse(&tokens, &mut diagnostics));
+    match document {
+        Ok(document) if diagnostics.is_empty() => document,
+        _ => panic!("generated schema does not parse: {text}"),
+    }
+}
+
+/// The schema field for `key`. It is required unless the type or the
+/// struct gives a value for a missing key.
+pub fn field_schema<T: FromStoa>(key: &str, has_default: bool) -> String {
+    let required = match has_default || T::missing().is_some() {
+        true => "",
+        false => ", required",
+    };
+    format!("{key} = {{ {}{required} }}", T::field_schema())
+}
+
+/// Reads the value of `key` in `block`, the value at `path`.
+pub fn field<T: FromStoa>(block: &KeyValueBlock, key: &str, path: &str, location: SourceLoc) -> Result<T> {
+    match find(block, key) {
+        Some(entry) => T::from_stoa(entry.value(), &format!("{path}.{key}"), entry.location()),
+        None => T::missing().ok_or_else(|| Error::MissingKey(format!("{path}.{key}"), location)),
+    }
+}
+
+/// Reads the value of `key` in `block`, or makes one if it is missing.
+pub fn field_or_else<T: FromStoa>(
+    block: &KeyValueBlock,
+    key: &str,
+    path: &str,
+    default: impl FnOnce() -> T,
+) -> Result<T> {
+    match find(block, key) {
+        Some(entry) => T::from_stoa(entry.value(), &format!("{path}.{key}"), entry.location()),
+        None => Ok(default()),
+    }
+}
+
+/// [`FromStoa::from_stoa`] for a type that implements [`Stoa`].
+pub fn from_block<T: Stoa>(value: &BlockValue, path: &str, location: SourceLoc) -> Result<T> {
+    match value {
+        BlockValue::Block(block) => T::from_block(block, path, location),
+        value => Err(Error::WrongType(path.to_string(), "a block", value.describe(), location)),
+    }
+}
+
+/// The last definition wins, as it does once duplicates are resolved.
+fn find<'a>(block: &'a KeyValueBlock, key: &str) -> Option<&'a KeyValueEntry> {
+    block
+        .entries
+        .iter()
+        .rev()
+        .find(|e| matches!(e.key(), Key::Name(name) if name == key))
+}
+
+/// The entries of a block value, each with its key path. A bare name stands
+/// for the string of that name, so `{ Table, Layout }` is a list of strings.
+fn items(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Vec<(String, BlockValue, SourceLoc)>> {
+    let BlockValue::Block(block) = value else {
+        return Err(Error::WrongType(path.to_string(), "a block", value.describe(), location));
+    };
+    Ok(block
+        .entries
+        .iter()
+        .map(|entry| {
+            let key = entry.key().to_string();
+            let value = match (entry.key(), entry.value()) {
+                (Key::Bare(name) | Key::MacroValue(name), BlockValue::Empty) => BlockValue::Literal(name.clone()),
+                (_, value) => value.clone(),
+            };
+            (key, value, entry.location())
+        })
+        .collect())
+}
+
+impl FromStoa for String {
+    fn field_schema() -> String {
+        "type = \"string\"".to_string()
+    }
+
+    fn from_stoa(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Self> {
+        match value {
+            BlockValue::Literal(s) => Ok(s.clone()),
+            value => Err(Error::WrongType(path.to_string(), "a string", value.describe(), location)),
+        }
+    }
+}
+
+macro_rules! integer {
+    ($($ty:ty => $schema:expr),* $(,)?) => {$(
+        impl FromStoa for $ty {
+            fn field_schema() -> String {
+                $schema.to_string()
+            }
+
+            fn from_stoa(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Self> {
+                match value {
+                    BlockValue::Integer(n) => <$ty>::try_from(*n)
+                        .map_err(|_| Error::OutOfRange(path.to_string(), stringify!($ty), location)),
+                    value => Err(Error::WrongType(path.to_string(), "an integer", value.describe(), location)),
+                }
+            }
+        }
+    )*};
+}
+
+// Documents cannot spell negative numbers, so signed types only need an upper
+// bound.
+integer! {
+    i8 => "type = \"integer\", max = 127",
+    i16 => "type = \"integer\", max = 32767",
+    i32 => "type = \"integer\", max = 2147483647",
+    i64 => "type = \"integer\"",
+    isize => "type = \"integer\"",
+    u8 => "type = \"integer\", min = 0, max = 255",
+    u16 => "type = \"integer\", min = 0, max = 65535",
+    u32 => "type = \"integer\", min = 0, max = 4294967295",
+    u64 => "type = \"integer\", min = 0",
+    usize => "type = \"integer\", min = 0",
+}
+
+macro_rules! float {
+    ($($ty:ty),*) => {$(
+        impl FromStoa for $ty {
+            fn field_schema() -> String {
+                "type = \"number\"".to_string()
+            }
+
+            fn from_stoa(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Self> {
+                match value {
+                    BlockValue::Integer(n) => Ok(*n as $ty),
+                    BlockValue::Float(n) => Ok(*n as $ty),
+                    value => Err(Error::WrongType(path.to_string(), "a number", value.describe(), location)),
+                }
+            }
+        }
+    )*};
+}
+
+float!(f32, f64);
+
+impl<T: FromStoa> FromStoa for Option<T> {
+    fn field_schema() -> String {
+        T::field_schema()
+    }
+
+    fn schema_name() -> Option<&'static str> {
+        T::schema_name()
+    }
+
+    fn schemas(schemas: &mut Vec<(&'static str, String)>) {
+        T::schemas(schemas)
+    }
+
+    fn from_stoa(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Self> {
+        T::from_stoa(value, path, location).map(Some)
+    }
+
+    fn missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+impl<T: FromStoa> FromStoa for Box<T> {
+    fn field_schema() -> String {
+        T::field_schema()
+    }
+
+    fn schema_name() -> Option<&'static str> {
+        T::schema_name()
+    }
+
+    fn schemas(schemas: &mut Vec<(&'static str, String)>) {
+        T::schemas(schemas)
+    }
+
+    fn from_stoa(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Self> {
+        T::from_stoa(value, path, location).map(Box::new)
+    }
+
+    fn missing() -> Option<Self> {
+        T::missing().map(Box::new)
+    }
+}
+
+/// The values of a block, in order. Their keys are ignored.
+impl<T: FromStoa> FromStoa for Vec<T> {
+    fn field_schema() -> String {
+        collection_schema::<T>()
+    }
+
+    fn schemas(schemas: &mut Vec<(&'static str, String)>) {
+        T::schemas(schemas)
+    }
+
+    fn from_stoa(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Self> {
+        items(value, path, location)?
+            .into_iter()
+            .map(|(key, value, location)| T::from_stoa(&value, &format!("{path}.{key}"), location))
+            .collect()
+    }
+}
+
+/// The entries of a block by key.
+impl<T: FromStoa> FromStoa for BTreeMap<String, T> {
+    fn field_schema() -> String {
+        collection_schema::<T>()
+    }
+
+    fn schemas(schemas: &mut Vec<(&'static str, String)>) {
+        T::schemas(schemas)
+    }
+
+    fn from_stoa(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Self> {
+        items(value, path, location)?
+            .into_iter()
+            .map(|(key, value, location)| {
+                let value = T::from_stoa(&value, &format!("{path}.{key}"), location)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+fn collection_schema<T: FromStoa>() -> String {
+    match T::schema_name() {
+        Some(name) => format!("type = \"block\", each = {name:?}"),
+        None => "type = \"block\"".to_string(),
+    }
+}
+
+/// Any block, kept as it is.
+impl FromStoa for KeyValueBlock {
+    fn field_schema() -> String {
+        "type = \"block\"".to_string()
+    }
+
+    fn from_stoa(value: &BlockValue, path: &str, location: SourceLoc) -> Result<Self> {
+        match value {
+            BlockValue::Block(block) => Ok(block.clone()),
+            value => Err(Error::WrongType(path.to_string(), "a block", value.describe(), location)),
+        }
+    }
+}
+
+/// Any value, kept as it is.
+impl FromStoa for BlockValue {
+    fn field_schema() -> String {
+        "type = \"any\"".to_string()
+    }
+
+    fn from_stoa(value: &BlockValue, _path: &str, _location: SourceLoc) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
@@ -0,0 +1,337 @@
+//! Checks on the meaning of a resolved document, beyond its syntax: that
+//! identifiers are well formed and unique, and that references name an entry.
+//!
+//! A reference is either a string under one of the [`REFERENCES`] keys, which
+//! names a top-level entry, or a `&Quotes.fields.id` value, which names any
+//! entry by its key path.
+
+use std::collections::HashMap;
+
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCategory},
+    keyvalue::{BlockValue, Key, KeyValueBlock, KeyValueEntry},
+};
+
+/// The key holding an entry's identifier.
+pub const ID: &str = "id";
+/// The key holding an entry's category. Identifiers only need to be unique
+/// among entries of the same type.
+pub const TYPE: &str = "type";
+/// The `type` of the entries that are tables.
+pub const TABLE_TYPE: &str = "Table";
+/// The `type` of the entries that are occurrences of a table.
+pub const OCCURRENCE_TYPE: &str = "Table Occurrence";
+
+/// The key naming the table a table occurrence is based on.
+pub const TABLE: &str = "table";
+/// Keys whose string value names another top-level entry, such as
+/// `table = "Machines"` in a table occurrence.
+pub const REFERENCES: &[&str] = &[TABLE];
+
+/// The value of `key` in the block of a top-level entry.
+pub fn value<'a>(entry: &'a KeyValueEntry, key: &str) -> Option<&'a KeyValueEntry> {
+    let BlockValue::Block(block) = entry.value() else {
+        return None;
+    };
+    block
+        .entries
+        .iter()
+        .rev()
+        .find(|e| matches!(e.key(), Key::Name(name) if name == key))
+}
+
+/// The `type` of a top-level entry, if it is a string.
+pub fn entry_type(entry: &KeyValueEntry) -> Option<&str> {
+    match value(entry, TYPE)?.value() {
+        BlockValue::Literal(kind) => Some(kind),
+        _ => None,
+    }
+}
+
+/// Checks the `id` of every top-level entry. Each must be an integer, and no
+/// two entries with the same `type` may share one. A collision is reported at
+/// the later entry and related to the first.
+pub fn ids(document: &KeyValueBlock) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut seen: HashMap<(Option<&str>, i64), (&KeyValueEntry, &KeyValueEntry)> = HashMap::new();
+
+    for entry in &document.entries {
+        let Some(id) = value(entry, ID) else {
+            continue;
+        };
+        let n = match id.value() {
+            BlockValue::Integer(n) => *n,
+            value => {
+                let diagnostic = Diagnostic::new(DiagnosticCategory::BadIDFormat, id.location(), String::new())
+                    .with_span(id.span())
+                    .with_message(format!("the id of `{}` should be an integer, found {}", entry.key(), value.describe()));
+                diagnostics.push(match value {
+                    BlockValue::Literal(s) if s.trim().parse::<i64>().is_ok() => {
+                        diagnostic.with_help(format!("write the id without quotes: `id = {}`", s.trim()))
+                    }
+                    _ => diagnostic,
+                });
+                continue;
+            }
+        };
+
+        let kind = entry_type(entry);
+        match seen.get(&(kind, n)) {
+            Some((first, first_id)) => {
+                let among = match kind {
+                    Some(kind) => format!("among entries of type \"{kind}\""),
+                    None => "among entries without a type".to_string(),
+                };
+                diagnostics.push(
+                    Diagnostic::new(DiagnosticCategory::DuplicateID, id.location(), String::new())
+                        .with_span(id.span())
+                        .with_message(format!("`{}` reuses id {n}, which `{}` already has", entry.key(), first.key()))
+                        .with_related(first_id.location(), first_id.span(), format!("id {n} is first used here"))
+                        .with_note(format!("ids must be unique {among}")),
+                );
+            }
+            None => {
+                seen.insert((kind, n), (entry, id));
+            }
+        }
+    }
+    diagnostics
+}
+
+/// The top-level entries of a document by name, to look references up in.
+#[derive(Debug)]
+pub struct References<'a> {
+    entries: HashMap<String, &'a KeyValueEntry>,
+}
+
+impl<'a> References<'a> {
+    pub fn new(document: &'a KeyValueBlock) -> Self {
+        let entries = document
+            .entries
+            .iter()
+            .filter_map(|entry| match entry.key() {
+                Key::Name(name) => Some((name.clone(), entry)),
+                _ => None,
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// The entry a value refers to: the top-level entry a string names, or
+    /// the entry at the end of a reference's key path.
+    pub fn resolve(&self, value: &BlockValue) -> Option<&'a KeyValueEntry> {
+        match value {
+            BlockValue::Literal(name) => self.entries.get(name).copied(),
+            BlockValue::Reference(path) => self.walk(path).ok(),
+            _ => None,
+        }
+    }
+
+    /// Follows a key path. When it leads nowhere, gives how many keys were
+    /// found and the names that could have come next.
+    fn walk(&self, path: &[String]) -> std::result::Result<&'a KeyValueEntry, (usize, Vec<&'a str>)> {
+        let top = || self.entries.values().copied().filter_map(name).collect();
+        let Some((first, rest)) = path.split_first() else {
+            return Err((0, top()));
+        };
+        let mut entry = *self.entries.get(first).ok_or_else(|| (0, top()))?;
+        for (i, key) in rest.iter().enumerate() {
+            let BlockValue::Block(block) = entry.value() else {
+                return Err((i + 1, vec![]));
+            };
+            let names = || block.entries.iter().filter_map(name);
+            entry = block
+                .entries
+                .iter()
+                .rev()
+                .find(|e| matches!(e.key(), Key::Name(name) if name == key))
+                .ok_or_else(|| (i + 1, names().collect()))?;
+        }
+        Ok(entry)
+    }
+}
+
+fn name(entry: &KeyValueEntry) -> Option<&str> {
+    match entry.key() {
+        Key::Name(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// The candidate closest to `name`, if one is close enough to be a typo.
+fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(d, _)| *d <= name.chars().count().max(3) / 3)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Checks that every reference names an entry.
+pub fn references(document: &KeyValueBlock) -> Vec<Diagnostic> {
+    let index = References::new(document);
+    let mut diagnostics = vec![];
+    paths(&index, document, &mut diagnostics);
+
+    for entry in &document.entries {
+        for key in REFERENCES {
+            let Some(reference) = value(entry, key) else {
+                continue;
+            };
+            if matches!(reference.value(), BlockValue::Reference(_)) || index.resolve(reference.value()).is_some() {
+                continue;
+            }
+            let diagnostic = Diagnostic::new(DiagnosticCategory::DanglingReference, reference.location(), String::new())
+                .with_span(reference.span());
+            diagnostics.push(match reference.value() {
+                BlockValue::Literal(name) => {
+                    let diagnostic = diagnostic.with_message(format!("`{}.{key}` refers to `{name}`, which is not defined", entry.key()));
+                    match suggest(name, index.entries.keys().map(String::as_str)) {
+                        Some(suggestion) => diagnostic.with_help(format!("did you mean `{suggestion}`?")),
+                        None => diagnostic,
+                    }
+                }
+                value => diagnostic.with_message(format!(
+                    "`{}.{key}` should name an entry, found {}",
+                    entry.key(),
+                    value.describe()
+                )),
+            });
+        }
+    }
+    diagnostics
+}
+
+/// `&key.path` values, recursively.
+fn paths(index: &References, block: &KeyValueBlock, diagnostics: &mut Vec<Diagnostic>) {
+    for entry in &block.entries {
+        match entry.value() {
+            BlockValue::Block(inner) => paths(index, inner, diagnostics),
+            BlockValue::Reference(path) => {
+                let Err((found, candidates)) = index.walk(path) else {
+                    continue;
+                };
+                let reference = format!("&{}", path.join("."));
+                let missing = &path[found.min(path.len() - 1)];
+                let message = match found {
+                    0 => format!("`{reference}` refers to `{missing}`, which is not defined"),
+                    n if candidates.is_empty() => format!("`{reference}` does not resolve: `{}` is not a block", path[..n].join(".")),
+                    n => format!("`{reference}` does not resolve: `{}` has no key `{missing}`", path[..n].join(".")),
+                };
+                let diagnostic = Diagnostic::new(DiagnosticCategory::DanglingReference, entry.location(), String::new())
+                    .with_span(entry.span())
+                    .with_message(message);
+                diagnostics.push(match suggest(missing, candidates) {
+                    Some(suggestion) => diagnostic.with_help(format!("did you mean `{suggestion}`?")),
+                    None => diagnostic,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The edit distance between two names.
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        diagnostic::DiagnosticCategory,
+        keyvalue::BlockValue,
+        testing::document,
+        token::SourceLoc,
+    };
+
+    use super::{ids, references, References};
+
+    #[test]
+    fn unique_ids() {
+        let text = "\
+Quotes = { id = 1, type = \"Table\" }
+Machines = { id = 1, type = \"Table Occurrence\" }
+Parts = { id = 1, type = \"Table\" }
+Notes = { id = \"4\", type = \"Table\" }
+Layouts = { id = 2.5 }
+";
+        let diagnostics = ids(&document(text));
+        let found = diagnostics
+            .iter()
+            .map(|d| (d.category(), d.location()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (DiagnosticCategory::DuplicateID, SourceLoc::new(3, 11)),
+                (DiagnosticCategory::BadIDFormat, SourceLoc::new(4, 11)),
+                (DiagnosticCategory::BadIDFormat, SourceLoc::new(5, 13)),
+            ]
+        );
+        assert_eq!(diagnostics[0].message(), "`Parts` reuses id 1, which `Quotes` already has");
+        assert_eq!(diagnostics[0].related()[0].location, SourceLoc::new(1, 12));
+        assert_eq!(diagnostics[1].help(), Some("write the id without quotes: `id = 4`"));
+    }
+
+    #[test]
+    fn resolves_references() {
+        let text = "\
+Machines = { id = 1, type = \"Table\" }
+Quotes Machines = { type = \"Table Occurrence\", table = \"Machines\" }
+Parts Machines = { type = \"Table Occurrence\", table = \"Machine\" }
+Notes = { table = 3 }
+";
+        let document = document(text);
+        let index = References::new(&document);
+        let occurrence = &document.entries[1];
+        let target = index.resolve(super::value(occurrence, "table").unwrap().value()).unwrap();
+        assert_eq!(target.location(), SourceLoc::new(1, 1));
+
+        let diagnostics = references(&document);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].category(), DiagnosticCategory::DanglingReference);
+        assert_eq!(diagnostics[0].location(), SourceLoc::new(3, 47));
+        assert_eq!(diagnostics[0].message(), "`Parts Machines.table` refers to `Machine`, which is not defined");
+        assert_eq!(diagnostics[0].help(), Some("did you mean `Machines`?"));
+        assert_eq!(diagnostics[1].message(), "`Notes.table` should name an entry, found an integer");
+    }
+
+    #[test]
+    fn resolves_paths() {
+        let text = "\
+Quotes = { id = 1, fields = { amount = { id = 7 } } }
+Quotes Machines = { table = &Quotes, key = &Quotes.fields.amount.id }
+Parts = { links = { a = &Quotes.field.amount, b = &Quotes.id.x, c = &Quote } }
+";
+        let document = document(text);
+        let index = References::new(&document);
+        let target = index.resolve(super::value(&document.entries[1], "key").unwrap().value()).unwrap();
+        assert_eq!(target.location(), SourceLoc::new(1, 42));
+        assert_eq!(target.value(), &BlockValue::Integer(7));
+
+        let diagnostics = references(&document);
+        let messages = diagnostics.iter().map(|d| (d.message(), d.help())).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                ("`&Quotes.field.amount` does not resolve: `Quotes` has no key `field`", Some("did you mean `fields`?")),
+                ("`&Quotes.id.x` does not resolve: `Quotes.id` is not a block", None),
+                ("`&Quote` refers to `Quote`, which is not defined", Some("did you mean `Quotes`?")),
+            ]
+        );
+        assert_eq!(diagnostics[0].location(), SourceLoc::new(3, 21));
+    }
+}
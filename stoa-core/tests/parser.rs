@@ -1,6 +1,6 @@
 use std::{fs::read_to_string, path::PathBuf};
 
-use stoa_core::{diagnostic::Diagnostic, lexer::lex, parser::{self, parse}};
+use stoa_core::{diagnostic::Diagnostic, lexer::lex, parser::parse};
 
 
 #[test]
@@ -0,0 +1,149 @@
+//! `#[derive(Stoa)]`, which reads a struct from a stoa block and describes the
+//! block it expects as a schema. See `stoa_core::typed` for the traits it
+//! implements.
+//!
+//! On the struct:
+//!
+//! - `#[stoa(rename = "Table Occurrence")]` names the schema. It defaults to
+//!   the name of the struct.
+//! - `#[stoa(when(type = "Table"))]` applies the schema to every top-level
+//!   entry whose block has those values.
+//!
+//! On a field:
+//!
+//! - `#[stoa(rename = "table name")]` reads the field from another key, such
+//!   as one of several words.
+//! - `#[stoa(default)]` uses `Default::default()` when the key is missing.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, Lit, LitStr, Type};
+
+#[proc_macro_derive(Stoa, attributes(stoa))]
+pub fn derive_stoa(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+struct Field {
+    ident: Ident,
+    ty: Type,
+    key: String,
+    default: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let mut schema_name = name.to_string();
+    let mut when = vec![];
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("stoa")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                schema_name = meta.value()?.parse::<LitStr>()?.value();
+            } else if meta.path.is_ident("when") {
+                meta.parse_nested_meta(|condition| {
+                    let key = condition.path.get_ident().map(Ident::to_string).ok_or_else(|| condition.error("expected a key"))?;
+                    let value = match condition.value()?.parse::<Lit>()? {
+                        Lit::Str(s) => format!("{:?}", s.value()),
+                        Lit::Int(n) => n.base10_digits().to_string(),
+                        Lit::Float(n) => n.base10_digits().to_string(),
+                        lit => return Err(syn::Error::new(lit.span(), "expected a string or a number")),
+                    };
+                    when.push(format!("{key} = {value}"));
+                    Ok(())
+                })?;
+            } else {
+                return Err(meta.error("expected `rename` or `when`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(&input.ident, "`Stoa` can only be derived for structs"));
+    };
+    let Fields::Named(named) = &data.fields else {
+        return Err(syn::Error::new_spanned(&input.ident, "`Stoa` can only be derived for structs with named fields"));
+    };
+
+    let mut fields = vec![];
+    for field in &named.named {
+        let ident = field.ident.clone().expect("named fields have names");
+        let mut key = ident.to_string().trim_start_matches("r#").to_string();
+        let mut default = false;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("stoa")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    key = meta.value()?.parse::<LitStr>()?.value();
+                } else if meta.path.is_ident("default") {
+                    default = true;
+                } else {
+                    return Err(meta.error("expected `rename` or `default`"));
+                }
+                Ok(())
+            })?;
+        }
+        fields.push(Field { ident, ty: field.ty.clone(), key, default });
+    }
+
+    let when = match when.is_empty() {
+        true => String::new(),
+        false => format!("when = {{ {} }}, ", when.join(", ")),
+    };
+    let field_schemas = fields.iter().map(|Field { ty, key, default, .. }| {
+        quote!(::stoa_core::typed::field_schema::<#ty>(#key, #default))
+    });
+    let types = fields.iter().map(|Field { ty, .. }| ty);
+    let conversions = fields.iter().map(|Field { ident, ty, key, default }| match default {
+        true => quote! {
+            #ident: ::stoa_core::typed::field_or_else::<#ty>(block, #key, path, ::core::default::Default::default)?
+        },
+        false => quote!(#ident: ::stoa_core::typed::field::<#ty>(block, #key, path, location)?),
+    });
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::stoa_core::typed::FromStoa for #name #ty_generics #where_clause {
+            fn field_schema() -> ::std::string::String {
+                ::std::format!("type = \"block\", schema = {:?}", <Self as ::stoa_core::typed::Stoa>::NAME)
+            }
+
+            fn schema_name() -> ::core::option::Option<&'static str> {
+                ::core::option::Option::Some(<Self as ::stoa_core::typed::Stoa>::NAME)
+            }
+
+            fn schemas(schemas: &mut ::std::vec::Vec<(&'static str, ::std::string::String)>) {
+                if ::stoa_core::typed::add_schema::<Self>(schemas) {
+                    #(<#types as ::stoa_core::typed::FromStoa>::schemas(schemas);)*
+                }
+            }
+
+            fn from_stoa(
+                value: &::stoa_core::keyvalue::BlockValue,
+                path: &str,
+                location: ::stoa_core::token::SourceLoc,
+            ) -> ::stoa_core::Result<Self> {
+                ::stoa_core::typed::from_block(value, path, location)
+            }
+        }
+
+        impl #impl_generics ::stoa_core::typed::Stoa for #name #ty_generics #where_clause {
+            const NAME: &'static str = #schema_name;
+
+            fn schema_body() -> ::std::string::String {
+                let fields: &[::std::string::String] = &[#(#field_schemas),*];
+                ::std::format!("{}fields = {{ {} }}", #when, fields.join(", "))
+            }
+
+            #[allow(unused_variables)]
+            fn from_block(
+                block: &::stoa_core::keyvalue::KeyValueBlock,
+                path: &str,
+                location: ::stoa_core::token::SourceLoc,
+            ) -> ::stoa_core::Result<Self> {
+                ::core::result::Result::Ok(Self { #(#conversions),* })
+            }
+        }
+    })
+}
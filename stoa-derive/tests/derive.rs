@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use stoa_core::{
+    keyvalue::KeyValueBlock,
+    lexer::lex,
+    parser::parse,
+    token::SourceLoc,
+    typed::{FromStoa, Stoa},
+};
+use stoa_derive::Stoa;
+
+#[derive(Debug, PartialEq, Stoa)]
+#[stoa(when(type = "Table"))]
+struct Table {
+    id: u32,
+    #[stoa(rename = "table name")]
+    name: Option<String>,
+    #[stoa(default)]
+    fields: BTreeMap<String, Field>,
+}
+
+#[derive(Debug, PartialEq, Stoa)]
+#[stoa(rename = "Field")]
+struct Field {
+    r#type: String,
+    tags: Vec<String>,
+}
+
+fn document(text: &str) -> KeyValueBlock {
+    let mut diagnostics = vec![];
+    let tokens = lex(text, &mut diagnostics).unwrap();
+    let document = parse(&tokens, &mut diagnostics).unwrap();
+    assert!(diagnostics.is_empty());
+    document
+}
+
+#[test]
+fn converts_blocks() {
+    let text = "\
+Quotes = {
+  type = \"Table\",
+  id = 3,
+  table name = \"Quotes\",
+  fields = { amount = { type = \"Number\", tags = { indexed, required } } }
+}
+";
+    let table = Table::from_entry(&document(text).entries[0]).unwrap();
+    let field = Field { r#type: "Number".to_string(), tags: vec!["indexed".to_string(), "required".to_string()] };
+    assert_eq!(
+        table,
+        Table { id: 3, name: Some("Quotes".to_string()), fields: BTreeMap::from([("amount".to_string(), field)]) }
+    );
+
+    let table = Table::from_entry(&document("Notes = { id = 4 }").entries[0]).unwrap();
+    assert_eq!(table, Table { id: 4, name: None, fields: BTreeMap::new() });
+}
+
+#[test]
+fn reports_located_errors() {
+    let text = "Quotes = {\n  fields = {\n    a = { type = 1, tags = {} }\n  }\n}\n";
+    let error = Table::from_entry(&document(text).entries[0]).unwrap_err();
+    assert_eq!(error.to_string(), "1:1: missing the required key `Quotes.id`");
+
+    let text = "Quotes = {\n  id = 1,\n  fields = {\n    a = { type = 1, tags = {} }\n  }\n}\n";
+    let error = Table::from_entry(&document(text).entries[0]).unwrap_err();
+    assert_eq!(error.location(), Some(SourceLoc::new(4, 11)));
+    assert_eq!(error.message(), "`Quotes.fields.a.type` should be a string, found an integer");
+
+    let error = u8::from_stoa(&stoa_core::keyvalue::BlockValue::Integer(300), "x", SourceLoc::new(1, 5)).unwrap_err();
+    assert_eq!(error.to_string(), "1:5: `x` does not fit in u8");
+}
+
+#[test]
+fn generates_schemas() {
+    assert_eq!(
+        Table::schema_source(),
+        "\
+Table = {
+  when = {
+    type = \"Table\"
+  },
+  fields = {
+    id = {
+      type = \"integer\",
+      min = 0,
+      max = 4294967295,
+      required
+    },
+    table name = {
+      type = \"string\"
+    },
+    fields = {
+      type = \"block\",
+      each = \"Field\"
+    }
+  }
+}
+
+Field = {
+  fields = {
+    type = {
+      type = \"string\",
+      required
+    },
+    tags = {
+      type = \"block\",
+      required
+    }
+  }
+}
+"
+    );
+
+    let schema = Table::schema();
+    let diagnostics = schema.validate(&document("Quotes = { type = \"Table\", fields = { a = { type = 2 } } }"));
+    let messages = diagnostics.iter().map(|d| d.message()).collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        [
+            "`Quotes` is missing the required key `id`",
+            "`Quotes.fields.a.type` should be a string, found an integer",
+            "`Quotes.fields.a` is missing the required key `tags`",
+        ]
+    );
+}
+
+#[derive(Debug, PartialEq, Stoa)]
+#[stoa(when(type = "Node"))]
+struct Node {
+    name: String,
+    #[stoa(default)]
+    children: Vec<Node>,
+    parent: Option<Box<Node>>,
+}
+
+#[test]
+fn generates_schemas_for_recursive_types() {
+    assert_eq!(
+        Node::schema_source(),
+        "\
+Node = {
+  when = {
+    type = \"Node\"
+  },
+  fields = {
+    name = {
+      type = \"string\",
+      required
+    },
+    children = {
+      type = \"block\",
+      each = \"Node\"
+    },
+    parent = {
+      type = \"block\",
+      schema = \"Node\"
+    }
+  }
+}
+"
+    );
+
+    let text = "\
+Root = { type = \"Node\", name = \"root\", children = { a = { name = \"a\", children = { b = { name = 1 } } } } }";
+    let root = document(text);
+    let diagnostics = Node::schema().validate(&root);
+    let messages = diagnostics.iter().map(|d| d.message()).collect::<Vec<_>>();
+    assert_eq!(messages, ["`Root.children.a.children.b.name` should be a string, found an integer"]);
+    let error = Node::from_entry(&root.entries[0]).unwrap_err();
+    assert_eq!(error.message(), messages[0]);
+}